@@ -0,0 +1,44 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Civil date from days since 1970-01-01 (Howard Hinnant's days_from_civil inverse)
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    let z: i64 = days + 719_468;
+    let era: i64 = z.div_euclid(146_097);
+    let doe: i64 = z.rem_euclid(146_097);
+    let yoe: i64 = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy: i64 = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp: i64 = (5 * doy + 2) / 153;
+    let day: u32 = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month: u32 = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year: i64 = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn main() {
+
+    let git_hash: String = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| String::from("unknown"));
+
+    // SOURCE_DATE_EPOCH keeps reproducible builds reproducible
+    let seconds: i64 = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs() as i64));
+    let (year, month, day) = civil_date(seconds.div_euclid(86_400));
+
+    println!("cargo:rustc-env=KMERS_GIT_HASH={}", git_hash);
+    println!("cargo:rustc-env=KMERS_BUILD_DATE={:04}-{:02}-{:02}", year, month, day);
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    for path in [".git/HEAD", ".git/refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs;
+use std::io;
+
+// User-defined alphabet: the valid characters and, optionally, their complements.
+// Windows containing any other character are not counted, canonicalization uses
+// the complement pairs instead of the built-in A/C/G/T rules.
+#[derive(Clone, Debug)]
+pub struct Alphabet {
+    valid: [bool; 256],
+    complement: [Option<u8>; 256],
+}
+
+impl Alphabet {
+
+    // One symbol per line, optionally followed by its complement ("A T"). Pairs
+    // apply in both directions, a symbol may be its own complement ("M M").
+    // Empty lines and lines starting with # are skipped.
+    pub fn parse(definition: &str) -> io::Result<Alphabet> {
+
+        let mut alphabet = Alphabet { valid: [false; 256], complement: [None; 256] };
+        let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData, format!("Alphabet line needs a symbol and an optional complement: {}", line));
+
+        for line in definition.lines() {
+            let line: &str = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let symbols: Vec<&str> = line.split_whitespace().collect();
+            if symbols.len() > 2 || symbols.iter().any(|symbol| symbol.len() != 1 || !symbol.is_ascii()) {
+                return Err(invalid(line));
+            }
+            let symbol: u8 = symbols[0].as_bytes()[0];
+            alphabet.valid[symbol as usize] = true;
+            if let Some(complement) = symbols.get(1) {
+                let complement: u8 = complement.as_bytes()[0];
+                alphabet.valid[complement as usize] = true;
+                alphabet.complement[symbol as usize] = Some(complement);
+                alphabet.complement[complement as usize] = Some(symbol);
+            }
+        }
+        Ok(alphabet)
+    }
+
+    pub fn load(path: &str) -> io::Result<Alphabet> {
+        Alphabet::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn contains(&self, symbol: u8) -> bool {
+        self.valid[symbol as usize]
+    }
+
+    // None if some symbol of the k-mer has no complement
+    pub fn reverse_complement(&self, kmer: &str) -> Option<String> {
+        kmer.bytes().rev().map(|symbol| self.complement[symbol as usize].map(char::from)).collect()
+    }
+
+    // Lexically smaller of a k-mer and its reverse complement, k-mers without a
+    // reverse complement are their own canonical form
+    pub fn canonical(&self, kmer: &str) -> String {
+        match self.reverse_complement(kmer) {
+            Some(reverse) if reverse.as_str() < kmer => reverse,
+            _ => kmer.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::count::{build_pool, count_kmers, CountOptions};
+    use crate::testing::temp_file;
+
+    // A/C/G/T plus a methylated C (M) that pairs with its own partner (H)
+    const METHYLATION: &[u8] = b"# methylation-aware\nA T\nC G\n\nM H\n";
+
+    #[test]
+    fn custom_complements_drive_canonicalization() {
+        let path: String = temp_file("methylation.alphabet", METHYLATION);
+        let alphabet: Alphabet = Alphabet::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(alphabet.contains(b'M') && alphabet.contains(b'H') && !alphabet.contains(b'N'));
+        assert_eq!(alphabet.reverse_complement("MAC").as_deref(), Some("GTH"));
+        assert_eq!(alphabet.canonical("MAC"), "GTH");
+        assert_eq!(alphabet.canonical("GTH"), "GTH");
+
+        let options: CountOptions = CountOptions { canonical: true, alphabet: Some(alphabet), ..CountOptions::default() };
+        let sequences: Vec<String> = vec![String::from("MACN"), String::from("GTH")];
+        let counts: HashMap<String, u32> = count_kmers(&sequences, 3, &build_pool(1).unwrap(), &options).unwrap();
+        assert_eq!(counts, HashMap::from([(String::from("GTH"), 2)]));
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        assert!(Alphabet::parse("A T G").is_err());
+        assert!(Alphabet::parse("AT").is_err());
+        assert_eq!(Alphabet::parse("M M").unwrap().canonical("MM"), "MM");
+    }
+}
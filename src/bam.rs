@@ -0,0 +1,165 @@
+use crate::error::KmerError;
+use crate::inflate::gunzip;
+use crate::kmer::reverse_complement;
+use crate::sequences::Record;
+
+// 4-bit base codes of BAM sequences
+const BASES: &[u8; 16] = b"=ACMGRSVTWYHKDBN";
+
+const FLAG_REVERSE: u16 = 0x10;
+const FLAG_SECONDARY: u16 = 0x100;
+const FLAG_SUPPLEMENTARY: u16 = 0x800;
+
+fn malformed(message: &str) -> KmerError {
+    KmerError::Decompress(format!("malformed BAM file: {}", message))
+}
+
+// Little-endian cursor over the decompressed BAM stream
+struct Cursor<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], KmerError> {
+        let bytes: &[u8] = self.data.get(self.position..self.position + count).ok_or_else(|| malformed("truncated"))?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, KmerError> {
+        let bytes: &[u8] = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+// Decodes one alignment record. Reads aligned to the reverse strand are stored
+// reverse complemented, they are turned back so every record matches the read
+// as sequenced (and the equivalent FASTQ).
+fn parse_record(record: &[u8]) -> Result<(u16, Record), KmerError> {
+
+    if record.len() < 32 {
+        return Err(malformed("truncated alignment record"));
+    }
+    let name_length: usize = record[8] as usize;
+    let cigar_operations: usize = u16::from_le_bytes([record[12], record[13]]) as usize;
+    let flag: u16 = u16::from_le_bytes([record[14], record[15]]);
+    let sequence_length: usize = u32::from_le_bytes([record[16], record[17], record[18], record[19]]) as usize;
+
+    let mut cursor = Cursor { data: record, position: 32 };
+    let name: &[u8] = cursor.bytes(name_length)?;
+    cursor.bytes(4 * cigar_operations)?;
+    let packed: &[u8] = cursor.bytes(sequence_length.div_ceil(2))?;
+    let qualities: &[u8] = cursor.bytes(sequence_length)?;
+
+    let id: String = String::from_utf8_lossy(name.strip_suffix(&[0]).unwrap_or(name)).into_owned();
+    let mut sequence: String = (0..sequence_length)
+        .map(|i| BASES[((packed[i / 2] >> (4 * (1 - i % 2))) & 0x0f) as usize] as char)
+        .collect();
+
+    // 0xff marks a missing quality string
+    let mut quality: Option<String> = if qualities.first().is_none_or(|&score| score == 0xff) {
+        None
+    } else {
+        Some(qualities.iter().map(|&score| (score.min(93) + 33) as char).collect())
+    };
+
+    if flag & FLAG_REVERSE != 0 {
+        sequence = reverse_complement(&sequence);
+        quality = quality.map(|quality| quality.chars().rev().collect());
+    }
+
+    Ok((flag, Record { id, sequence, quality }))
+}
+
+// All reads of a BAM file, optionally without secondary and supplementary
+// alignments (which repeat a read that already has a primary alignment)
+pub fn bam_records(compressed: &[u8], skip_secondary: bool) -> Result<Vec<Record>, KmerError> {
+
+    let data: Vec<u8> = gunzip(compressed)?;
+    let mut cursor = Cursor { data: &data, position: 0 };
+
+    if cursor.bytes(4)? != b"BAM\x01" {
+        return Err(malformed("missing BAM magic"));
+    }
+    let text_length: usize = cursor.u32()? as usize;
+    cursor.bytes(text_length)?;
+    let references: u32 = cursor.u32()?;
+    for _ in 0..references {
+        let name_length: usize = cursor.u32()? as usize;
+        cursor.bytes(name_length + 4)?;
+    }
+
+    let mut records: Vec<Record> = Vec::new();
+    while cursor.position < data.len() {
+        let block_size: usize = cursor.u32()? as usize;
+        let (flag, record) = parse_record(cursor.bytes(block_size)?)?;
+        if skip_secondary && flag & (FLAG_SECONDARY | FLAG_SUPPLEMENTARY) != 0 {
+            continue;
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequences::{get_records, ReadOptions};
+
+    // Unaligned record of the read ACGTN with qualities 0, 10, 20, 30, 40
+    fn record(flag: u16) -> Vec<u8> {
+        let mut record: Vec<u8> = vec![0; 32];
+        record[8] = 3;
+        record[14..16].copy_from_slice(&flag.to_le_bytes());
+        record[16..20].copy_from_slice(&5u32.to_le_bytes());
+        record.extend_from_slice(b"r1\0");
+        record.extend_from_slice(&[0x12, 0x48, 0xf0]);
+        record.extend_from_slice(&[0, 10, 20, 30, 40]);
+        record
+    }
+
+    #[test]
+    fn forward_record() {
+        let (flag, record) = parse_record(&record(0)).unwrap();
+        assert_eq!(flag, 0);
+        assert_eq!(record.id, "r1");
+        assert_eq!(record.sequence, "ACGTN");
+        assert_eq!(record.quality.as_deref(), Some("!+5?I"));
+    }
+
+    #[test]
+    fn reverse_record() {
+        let (flag, record) = parse_record(&record(FLAG_REVERSE | FLAG_SECONDARY)).unwrap();
+        assert_eq!(flag, FLAG_REVERSE | FLAG_SECONDARY);
+        assert_eq!(record.sequence, "NACGT");
+        assert_eq!(record.quality.as_deref(), Some("I?5+!"));
+    }
+
+    #[test]
+    fn missing_quality() {
+        let mut bytes: Vec<u8> = record(0);
+        let length: usize = bytes.len();
+        bytes[length - 5..].fill(0xff);
+        assert_eq!(parse_record(&bytes).unwrap().1.quality, None);
+    }
+
+    #[test]
+    fn truncated_record() {
+        let bytes: Vec<u8> = record(0);
+        assert!(matches!(parse_record(&bytes[..bytes.len() - 1]), Err(KmerError::Decompress(_))));
+    }
+
+    #[test]
+    fn tiny_bam_matches_fastq() {
+        let bam: &[u8] = include_bytes!("../fixtures/tiny.bam");
+        let fastq: Vec<Record> = get_records(&String::from("fixtures/tiny.fq"), &ReadOptions::default()).unwrap();
+        let primary: Vec<Record> = bam_records(bam, true).unwrap();
+        assert_eq!(bam_records(bam, false).unwrap().len(), fastq.len() + 2);
+        assert_eq!(primary.len(), fastq.len());
+        for (read, expected) in primary.iter().zip(&fastq) {
+            assert_eq!(read.sequence, expected.sequence);
+        }
+    }
+}
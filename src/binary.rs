@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::counter::Count;
+use crate::output::{ordered, Output, SortOrder};
+
+pub const BINARY_FILE: &str = "kmer_counts.bin";
+
+// File header: magic, format version, k as a little-endian u32
+const MAGIC: &[u8; 7] = b"KMERBIN";
+const VERSION: u8 = 1;
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Unsigned LEB128: 7 bits per byte, least significant group first, the high bit
+// marks that another byte follows. Counts below 128 take a single byte.
+pub fn write_varint(writer: &mut impl Write, mut value: u64) -> io::Result<()> {
+    while value >= 0x80 {
+        writer.write_all(&[(value as u8 & 0x7f) | 0x80])?;
+        value >>= 7;
+    }
+    writer.write_all(&[value as u8])
+}
+
+pub fn read_varint(reader: &mut impl Read) -> io::Result<u64> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte: [u8; 1] = [0];
+        reader.read_exact(&mut byte)?;
+        let bits: u64 = (byte[0] & 0x7f) as u64;
+        if shift == 63 && bits > 1 {
+            break;
+        }
+        value |= bits << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid(String::from("Varint longer than 64 bits")))
+}
+
+// Bytes of a packed k-mer: 2 bits per base, four bases per byte
+fn packed_len(k: usize) -> usize {
+    k.div_ceil(4)
+}
+
+// Packs an uppercase A/C/G/T k-mer, first base in the highest bits of the first
+// byte (so packed keys sort like the strings). None for any other base.
+fn pack_kmer(kmer: &str, packed: &mut Vec<u8>) -> Option<()> {
+    for chunk in kmer.as_bytes().chunks(4) {
+        let mut byte: u8 = 0;
+        for (i, base) in chunk.iter().enumerate() {
+            let code: u8 = match base {
+                b'A' => 0,
+                b'C' => 1,
+                b'G' => 2,
+                b'T' => 3,
+                _ => return None,
+            };
+            byte |= code << (6 - 2 * i);
+        }
+        packed.push(byte);
+    }
+    Some(())
+}
+
+fn unpack_kmer(packed: &[u8], k: usize) -> String {
+    (0..k).map(|i| b"ACGT"[((packed[i / 4] >> (6 - 2 * (i % 4))) & 3) as usize] as char).collect()
+}
+
+// Header followed by one packed k-mer key and LEB128 count per entry, typically a
+// fraction of the TSV size. Only plain uppercase A/C/G/T k-mers can be packed.
+pub fn save_binary<C: Count>(kmer_hashmap: HashMap<String, C>, path: &str, buffer: usize, sort: SortOrder) -> io::Result<()> {
+
+    let k: usize = kmer_hashmap.keys().next().map_or(0, String::len);
+    let mut file = Output::create(path, buffer)?;
+
+    file.write_all(MAGIC)?;
+    file.write_all(&[VERSION])?;
+    file.write_all(&(k as u32).to_le_bytes())?;
+
+    let mut packed: Vec<u8> = Vec::with_capacity(packed_len(k));
+    for (kmer, count) in ordered(kmer_hashmap.into_iter(), sort) {
+        packed.clear();
+        if kmer.len() != k || pack_kmer(&kmer, &mut packed).is_none() {
+            return Err(invalid(format!("Binary output needs uppercase A/C/G/T k-mers of one length: {}", kmer)));
+        }
+        file.write_all(&packed)?;
+        write_varint(&mut file, count.to_u64())?;
+    }
+
+    file.flush()
+}
+
+pub fn is_binary(path: &str) -> io::Result<bool> {
+    let mut magic: [u8; 7] = [0; 7];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == MAGIC),
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+// Counts of a binary table, summed per k-mer like load_counts
+pub fn load_binary(path: &str) -> io::Result<HashMap<String, u64>> {
+
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut header: [u8; 12] = [0; 12];
+    reader.read_exact(&mut header)?;
+    if &header[..7] != MAGIC || header[7] != VERSION {
+        return Err(invalid(format!("{} is not a kmeRS binary table of this version", path)));
+    }
+    let k: usize = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+
+    let mut kmer_hashmap: HashMap<String, u64> = HashMap::new();
+    let mut packed: Vec<u8> = vec![0; packed_len(k)];
+    loop {
+        // A clean end of file can only occur before a key
+        if reader.fill_buf()?.is_empty() {
+            break;
+        }
+        reader.read_exact(&mut packed)?;
+        let count: u64 = read_varint(&mut reader)?;
+        let total: &mut u64 = kmer_hashmap.entry(unpack_kmer(&packed, k)).or_insert(0);
+        *total = total.checked_add(count).ok_or_else(|| invalid(format!("Summed count overflows u64 in {}", path)))?;
+    }
+
+    Ok(kmer_hashmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::output::{load_counts, DEFAULT_WRITE_BUFFER};
+    use crate::testing::temp_path;
+
+    #[test]
+    fn varints_round_trip() {
+        let values: [u64; 9] = [0, 1, 127, 128, 300, 16_383, 16_384, u32::MAX as u64 + 1, u64::MAX];
+        let mut bytes: Vec<u8> = Vec::new();
+        for value in values {
+            write_varint(&mut bytes, value).unwrap();
+        }
+        // 1 + 1 + 1 + 2 + 2 + 2 + 3 + 5 + 10 bytes
+        assert_eq!(bytes.len(), 27);
+        assert_eq!(&bytes[3..5], [0x80, 0x01]);
+
+        let mut reader: &[u8] = &bytes;
+        for value in values {
+            assert_eq!(read_varint(&mut reader).unwrap(), value);
+        }
+        assert!(reader.is_empty());
+        assert!(read_varint(&mut &[0xff; 11][..]).is_err());
+        assert!(read_varint(&mut &[0x80][..]).is_err());
+    }
+
+    // The binary table reads back as the TSV it was made from
+    #[test]
+    fn binary_table_round_trips() {
+        let mut counts: HashMap<String, u64> = load_counts("fixtures/legacy.k3.tsv").unwrap();
+        counts.insert(String::from("TTT"), u64::MAX);
+        let path: String = temp_path("counts.bin");
+        save_binary(counts.clone(), &path, DEFAULT_WRITE_BUFFER, SortOrder::Lexical).unwrap();
+        assert!(is_binary(&path).unwrap());
+        assert_eq!(load_binary(&path).unwrap(), counts);
+        fs::remove_file(&path).unwrap();
+
+        assert!(!is_binary("fixtures/legacy.k3.tsv").unwrap());
+        let path: String = temp_path("soft_masked.bin");
+        assert!(save_binary(HashMap::from([(String::from("acg"), 1u32)]), &path, DEFAULT_WRITE_BUFFER, SortOrder::Lexical).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Number of bit positions set per key
+const NUM_HASHES: u64 = 3;
+
+// Lock-free Bloom filter shared by all counting threads
+pub struct BloomFilter {
+    words: Vec<AtomicU64>,
+    num_bits: u64,
+}
+
+impl BloomFilter {
+
+    pub fn new(num_bits: usize) -> BloomFilter {
+        let num_words: usize = num_bits.div_ceil(64).max(1);
+        BloomFilter {
+            words: (0..num_words).map(|_| AtomicU64::new(0)).collect(),
+            num_bits: (num_words * 64) as u64,
+        }
+    }
+
+    pub fn with_megabytes(megabytes: usize) -> BloomFilter {
+        BloomFilter::new(megabytes * 8 * 1024 * 1024)
+    }
+
+    // Double hashing: positions are h1 + i * h2 for i in 0..NUM_HASHES
+    fn hashes(key: &str) -> (u64, u64) {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let h1: u64 = hasher.finish();
+        0x9e37_79b9_7f4a_7c15_u64.hash(&mut hasher);
+        let h2: u64 = hasher.finish() | 1;
+        (h1, h2)
+    }
+
+    // Sets the key's bits and returns whether all of them were already set,
+    // i.e. whether the key has (probably) been inserted before
+    pub fn insert(&self, key: &str) -> bool {
+
+        let (h1, h2) = BloomFilter::hashes(key);
+        let mut seen: bool = true;
+
+        for i in 0..NUM_HASHES {
+            let bit: u64 = h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits;
+            let mask: u64 = 1 << (bit % 64);
+            let previous: u64 = self.words[(bit / 64) as usize].fetch_or(mask, Ordering::Relaxed);
+            if previous & mask == 0 {
+                seen = false;
+            }
+        }
+        seen
+    }
+}
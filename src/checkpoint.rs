@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem::size_of;
+use std::sync::atomic::AtomicBool;
+use rayon::ThreadPool;
+
+use crate::checksum::fnv1a;
+use crate::count::{add_count, count_kmers, overflow_error, CountOptions};
+use crate::counter::Count;
+use crate::error::KmerError;
+use crate::{debug, info};
+
+const MAGIC: &[u8; 8] = b"KMERCKPT";
+const VERSION: u32 = 2;
+
+// State of an interrupted count: the k-mers of sequences[..next_sequence]
+#[derive(Clone, Debug)]
+pub struct Checkpoint<C> {
+    pub k: usize,
+    // Number of sequences of the run, a resumed run must read the same input
+    pub sequences: usize,
+    // Every option the counts depend on, see count_kmers_checkpointed; a resumed
+    // run must use the same
+    pub settings: String,
+    pub next_sequence: usize,
+    pub counts: HashMap<String, C>,
+}
+
+// Where and how often count_kmers_checkpointed saves checkpoints
+#[derive(Clone, Debug)]
+pub struct CheckpointOptions {
+    // Sequences per chunk, a checkpoint is saved after each
+    pub every: usize,
+    pub path: String,
+    // The options that shape the sequences before counting (trimming, filters,
+    // ...) as text, they are stored along with those of CountOptions
+    pub input_settings: String,
+}
+
+// The options of a count that change its result, as "name=value" pairs: the
+// counter type and the CountOptions that select and transform the windows
+// (stop, deadline, progress and strict do not change complete counts)
+pub fn count_settings<C: Count>(options: &CountOptions) -> String {
+    let minimizers: String = options.canonical_minimizers.map_or(String::from("none"), |window| window.to_string());
+    let alphabet: String = options.alphabet.as_ref().map_or(String::from("none"), |alphabet| format!("{:016x}", fnv1a(format!("{:?}", alphabet).as_bytes())));
+    format!(
+        "count-type=u{} canonical={} strand={:?} skip-masked={} ignore-chars={:?} canonical-minimizers={} minimizer-tiebreak={:?} central-wildcard={} alphabet={} bloom={:?}",
+        size_of::<C>() * 8, options.canonical, options.strand, options.skip_masked, String::from_utf8_lossy(&options.ignore_chars),
+        minimizers, options.minimizer_tiebreak, options.central_wildcard, alphabet, options.bloom_megabytes
+    )
+}
+
+fn write_u32(writer: &mut impl Write, value: usize) -> io::Result<()> {
+    writer.write_all(&(value as u32).to_le_bytes())
+}
+
+// Checkpoint written next to the output
+pub fn checkpoint_path(output: &str) -> String {
+    format!("{}.checkpoint", output)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid checkpoint: {}", message))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes: [u8; 4] = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes: [u8; 8] = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+// Little-endian binary layout, see the README. The file is written under a
+// temporary name and renamed, so an interruption while writing leaves the
+// previous checkpoint intact.
+pub fn save_checkpoint<C: Count>(checkpoint: &Checkpoint<C>, path: &str) -> io::Result<()> {
+
+    let temporary: String = format!("{}.tmp", path);
+    let mut file = BufWriter::new(File::create(&temporary)?);
+
+    file.write_all(MAGIC)?;
+    file.write_all(&VERSION.to_le_bytes())?;
+    write_u32(&mut file, checkpoint.k)?;
+    file.write_all(&(checkpoint.sequences as u64).to_le_bytes())?;
+    write_u32(&mut file, checkpoint.settings.len())?;
+    file.write_all(checkpoint.settings.as_bytes())?;
+    file.write_all(&(checkpoint.next_sequence as u64).to_le_bytes())?;
+    file.write_all(&(checkpoint.counts.len() as u64).to_le_bytes())?;
+    for (kmer, count) in &checkpoint.counts {
+        write_u32(&mut file, kmer.len())?;
+        file.write_all(kmer.as_bytes())?;
+        file.write_all(&count.to_u64().to_le_bytes())?;
+    }
+    file.flush()?;
+    drop(file);
+
+    fs::rename(temporary, path)
+}
+
+pub fn load_checkpoint<C: Count>(path: &str) -> io::Result<Checkpoint<C>> {
+
+    let mut file = BufReader::new(File::open(path)?);
+
+    let mut magic: [u8; 8] = [0; 8];
+    file.read_exact(&mut magic)?;
+    if &magic != MAGIC || read_u32(&mut file)? != VERSION {
+        return Err(invalid("not a kmeRS checkpoint of this version"));
+    }
+    let k: usize = read_u32(&mut file)? as usize;
+    let sequences: usize = read_u64(&mut file)? as usize;
+    let mut settings: Vec<u8> = vec![0; read_u32(&mut file)? as usize];
+    file.read_exact(&mut settings)?;
+    let settings: String = String::from_utf8(settings).map_err(|_| invalid("settings are not UTF-8"))?;
+    let next_sequence: usize = read_u64(&mut file)? as usize;
+    let entries: u64 = read_u64(&mut file)?;
+
+    let mut counts: HashMap<String, C> = HashMap::new();
+    for _ in 0..entries {
+        let length: usize = read_u32(&mut file)? as usize;
+        let mut kmer: Vec<u8> = vec![0; length];
+        file.read_exact(&mut kmer)?;
+        let kmer: String = String::from_utf8(kmer).map_err(|_| invalid("k-mer is not UTF-8"))?;
+        let count: C = C::from_u64(read_u64(&mut file)?).ok_or_else(|| invalid("count does not fit the --count-type"))?;
+        counts.insert(kmer, count);
+    }
+
+    Ok(Checkpoint { k, sequences, settings, next_sequence, counts })
+}
+
+// count_kmers over consecutive chunks of every sequences, saving a checkpoint
+// after each completed chunk. A resumed run starts after the sequences the
+// checkpoint already covers, it must have the same k, number of sequences and
+// settings (count_settings and the input settings). Counts equal those of
+// count_kmers; Ctrl-C keeps the last complete checkpoint.
+pub fn count_kmers_checkpointed<C: Count>(
+    sequences: &[String],
+    k: usize,
+    pool: &ThreadPool,
+    options: &CountOptions,
+    checkpointing: &CheckpointOptions,
+    resume: Option<Checkpoint<C>>,
+) -> Result<HashMap<String, C>, KmerError> {
+
+    let settings: String = format!("{} {}", count_settings::<C>(options), checkpointing.input_settings).trim_end().to_string();
+    let mut checkpoint: Checkpoint<C> = match resume {
+        Some(checkpoint) => {
+            if checkpoint.k != k || checkpoint.sequences != sequences.len() || checkpoint.next_sequence > sequences.len() {
+                return Err(KmerError::Argument(format!(
+                    "the checkpoint was written for k = {} and {} sequences, this run has k = {} and {}",
+                    checkpoint.k, checkpoint.sequences, k, sequences.len()
+                )));
+            }
+            if checkpoint.settings != settings {
+                // Only the name=value pairs that differ
+                let differing = |of: &str, other: &str| of.split(' ').filter(|pair| !other.split(' ').any(|other| other == *pair)).collect::<Vec<&str>>().join(" ");
+                return Err(KmerError::Argument(format!(
+                    "the checkpoint was written with {}, this run has {}",
+                    differing(&checkpoint.settings, &settings), differing(&settings, &checkpoint.settings)
+                )));
+            }
+            info!("Resuming after {} of {} sequences", checkpoint.next_sequence, sequences.len());
+            checkpoint
+        }
+        None => Checkpoint { k, sequences: sequences.len(), settings, next_sequence: 0, counts: HashMap::new() },
+    };
+
+    let overflowed = AtomicBool::new(false);
+
+    while checkpoint.next_sequence < sequences.len() {
+        let end: usize = checkpoint.next_sequence.saturating_add(checkpointing.every).min(sequences.len());
+        let chunk: HashMap<String, C> = count_kmers(&sequences[checkpoint.next_sequence..end], k, pool, options)?;
+        for (kmer, count) in chunk {
+            add_count(checkpoint.counts.entry(kmer).or_default(), count, &overflowed, options);
+        }
+        overflow_error::<C>(&overflowed, options)?;
+        if options.stopped() {
+            break;
+        }
+        checkpoint.next_sequence = end;
+        save_checkpoint(&checkpoint, &checkpointing.path)?;
+        debug!("Saved a checkpoint after {} sequences to {}", end, checkpointing.path);
+    }
+
+    Ok(checkpoint.counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::count::build_pool;
+    use crate::sequences::{get_records, ReadOptions};
+    use crate::testing::temp_path;
+
+    fn sequences() -> Vec<String> {
+        get_records(&"fixtures/tiny.fq".to_string(), &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect()
+    }
+
+    // The checkpoint an interrupted run leaves after its first 10 sequences
+    fn interrupted(sequences: &[String], options: &CountOptions, checkpointing: &CheckpointOptions) -> Checkpoint<u32> {
+        let counts: HashMap<String, u32> = count_kmers(&sequences[..10], 5, &build_pool(2).unwrap(), options).unwrap();
+        let settings: String = format!("{} {}", count_settings::<u32>(options), checkpointing.input_settings);
+        save_checkpoint(&Checkpoint { k: 5, sequences: sequences.len(), settings, next_sequence: 10, counts }, &checkpointing.path).unwrap();
+        load_checkpoint(&checkpointing.path).unwrap()
+    }
+
+    #[test]
+    fn resumed_counts_equal_an_uninterrupted_run() {
+        let sequences: Vec<String> = sequences();
+        let pool: ThreadPool = build_pool(2).unwrap();
+        let options: CountOptions = CountOptions::default();
+        let checkpointing = CheckpointOptions { every: 7, path: temp_path("resume.checkpoint"), input_settings: String::from("trim-qual=Some(20)") };
+
+        let resume: Checkpoint<u32> = interrupted(&sequences, &options, &checkpointing);
+        assert_eq!(resume.next_sequence, 10);
+        let resumed: HashMap<String, u32> = count_kmers_checkpointed(&sequences, 5, &pool, &options, &checkpointing, Some(resume)).unwrap();
+        assert_eq!(resumed, count_kmers::<u32>(&sequences, 5, &pool, &options).unwrap());
+        assert_eq!(load_checkpoint::<u32>(&checkpointing.path).unwrap().next_sequence, sequences.len());
+        fs::remove_file(&checkpointing.path).unwrap();
+    }
+
+    #[test]
+    fn resuming_with_other_options_is_an_error() {
+        let sequences: Vec<String> = sequences();
+        let pool: ThreadPool = build_pool(2).unwrap();
+        let options: CountOptions = CountOptions::default();
+        let checkpointing = CheckpointOptions { every: 7, path: temp_path("mismatch.checkpoint"), input_settings: String::from("trim-qual=Some(20)") };
+
+        let canonical: CountOptions = CountOptions { canonical: true, ..CountOptions::default() };
+        let resume: Checkpoint<u32> = interrupted(&sequences, &options, &checkpointing);
+        let result = count_kmers_checkpointed(&sequences, 5, &pool, &canonical, &checkpointing, Some(resume));
+        assert!(matches!(result, Err(KmerError::Argument(_))));
+
+        let other_input = CheckpointOptions { input_settings: String::from("trim-qual=Some(30)"), ..checkpointing.clone() };
+        let resume: Checkpoint<u32> = interrupted(&sequences, &options, &checkpointing);
+        let result = count_kmers_checkpointed(&sequences, 5, &pool, &options, &other_input, Some(resume));
+        assert!(matches!(result, Err(KmerError::Argument(_))));
+
+        // The counter type is part of the settings too
+        let resume: Checkpoint<u32> = interrupted(&sequences, &options, &checkpointing);
+        let widened: Checkpoint<u64> = Checkpoint { k: resume.k, sequences: resume.sequences, settings: resume.settings, next_sequence: resume.next_sequence, counts: HashMap::new() };
+        let result = count_kmers_checkpointed(&sequences, 5, &pool, &options, &checkpointing, Some(widened));
+        assert!(matches!(result, Err(KmerError::Argument(_))));
+        fs::remove_file(&checkpointing.path).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use crate::counter::Count;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// 64-bit FNV-1a, implemented here because std's DefaultHasher is not
+// guaranteed to stay the same across Rust releases
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+// FNV-1a of a byte string, e.g. a k-mer
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv1a(FNV_OFFSET);
+    hasher.write(bytes);
+    hasher.0
+}
+
+// Order-independent checksum of a count table: the pairs are sorted by k-mer and
+// hashed as "kmer\tcount\n" lines, i.e. the hash of the lexically sorted TSV output
+pub fn checksum<C: Count>(kmer_hashmap: &HashMap<String, C>) -> u64 {
+
+    let mut pairs: Vec<(&String, &C)> = kmer_hashmap.iter().collect();
+    pairs.sort_unstable();
+
+    let mut hasher = Fnv1a(FNV_OFFSET);
+    for (key, value) in pairs {
+        hasher.write(key.as_bytes());
+        hasher.write(b"\t");
+        hasher.write(value.to_string().as_bytes());
+        hasher.write(b"\n");
+    }
+    hasher.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::count::{build_pool, count_kmers, CountOptions};
+    use crate::output::load_counts;
+    use crate::sequences::{get_records, ReadOptions};
+
+    // The hash of the lexically sorted TSV, fixed across runs and builds
+    #[test]
+    fn checksum_hashes_the_sorted_table() {
+        let counts: HashMap<String, u64> = load_counts("fixtures/legacy.k3.tsv").unwrap();
+        assert_eq!(checksum(&counts), fnv1a(&fs::read("fixtures/legacy.k3.tsv").unwrap()));
+        assert_eq!(checksum(&counts), 0xe0cd_6d9d_6842_7ef7);
+    }
+
+    #[test]
+    fn checksum_is_stable_and_sensitive() {
+        let sequences: Vec<String> = get_records(&"fixtures/tiny.fq".to_string(), &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect();
+        let run = |threads: usize| -> HashMap<String, u32> { count_kmers(&sequences, 5, &build_pool(threads).unwrap(), &CountOptions::default()).unwrap() };
+
+        let mut counts: HashMap<String, u32> = run(1);
+        assert_eq!(checksum(&counts), checksum(&run(1)));
+        assert_eq!(checksum(&counts), checksum(&run(4)));
+
+        let reference: u64 = checksum(&counts);
+        *counts.values_mut().next().unwrap() += 1;
+        assert_ne!(checksum(&counts), reference);
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::{HashMap, HashSet};
+use rayon::{ThreadPool, prelude::*};
+
+use crate::counter::Count;
+use crate::kmer::canonical_kmer;
+
+// A k-mer is only folded into a neighbor with at least this many times its count
+pub const DEFAULT_COLLAPSE_RATIO: u64 = 10;
+
+// Most abundant k-mer at Hamming distance 1 (substitutions by A/C/G/T), ties go
+// to the lexically smaller neighbor. In canonical mode the neighbors are looked
+// up under their canonical form.
+fn best_neighbor<C: Count>(kmer: &str, kmer_hashmap: &HashMap<String, C>, canonical: bool) -> Option<(String, C)> {
+
+    let mut best: Option<(String, C)> = None;
+    let mut neighbor: Vec<u8> = kmer.as_bytes().to_vec();
+
+    for position in 0..neighbor.len() {
+        let original: u8 = neighbor[position];
+        for &base in b"ACGT" {
+            if base == original.to_ascii_uppercase() {
+                continue;
+            }
+            neighbor[position] = base;
+            let mut key: String = String::from_utf8_lossy(&neighbor).into_owned();
+            if canonical {
+                key = canonical_kmer(&key);
+            }
+            if key != kmer {
+                if let Some(&count) = kmer_hashmap.get(&key) {
+                    let better: bool = match &best {
+                        Some((best_key, best_count)) => count > *best_count || (count == *best_count && key < *best_key),
+                        None => true,
+                    };
+                    if better {
+                        best = Some((key, count));
+                    }
+                }
+            }
+        }
+        neighbor[position] = original;
+    }
+    best
+}
+
+// Folds likely sequencing errors into the k-mer they were read from: every k-mer
+// whose most abundant Hamming-1 neighbor has at least `ratio` times its count
+// hands its count to that neighbor and disappears. Decisions are made on the
+// original counts and a k-mer that is folded away never absorbs others, so the
+// result does not depend on iteration order. Costs 3k lookups per k-mer.
+// Returns the number of collapsed k-mers.
+pub fn collapse_errors<C: Count>(kmer_hashmap: &mut HashMap<String, C>, ratio: u64, canonical: bool, pool: &ThreadPool) -> usize {
+
+    let moves: Vec<(String, String)> = pool.install(|| {
+        kmer_hashmap.par_iter()
+            .filter_map(|(kmer, &count)| {
+                let (neighbor, neighbor_count) = best_neighbor(kmer, kmer_hashmap, canonical)?;
+                if neighbor_count.to_u64() >= count.to_u64().saturating_mul(ratio) {
+                    Some((kmer.clone(), neighbor))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    });
+
+    let sources: HashSet<&String> = moves.iter().map(|(kmer, _)| kmer).collect();
+    let mut collapsed: usize = 0;
+
+    for (kmer, neighbor) in &moves {
+        if sources.contains(neighbor) {
+            continue;
+        }
+        let count: C = kmer_hashmap.remove(kmer).unwrap();
+        let target: &mut C = kmer_hashmap.get_mut(neighbor).unwrap();
+        *target = target.checked_add(count).unwrap_or(C::MAX);
+        collapsed += 1;
+    }
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::count::{build_pool, count_kmers, CountOptions};
+    use crate::output::save_jellyfish;
+    use crate::sequences::{get_records, ReadOptions};
+    use crate::testing::temp_path;
+
+    fn collapse_sequences() -> Vec<String> {
+        get_records(&"fixtures/collapse.fa".to_string(), &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect()
+    }
+
+    // AAC is one base from TAC, the reverse complement of the 20 times counted GTA
+    #[test]
+    fn canonical_singleton_is_absorbed() {
+        let pool: ThreadPool = build_pool(2).unwrap();
+        let options: CountOptions = CountOptions { canonical: true, ..CountOptions::default() };
+        let mut counts: HashMap<String, u32> = count_kmers(&collapse_sequences(), 3, &pool, &options).unwrap();
+        assert_eq!(counts.len(), 2);
+
+        assert_eq!(collapse_errors(&mut counts, DEFAULT_COLLAPSE_RATIO, true, &pool), 1);
+        let path: String = temp_path("collapse.jf.txt");
+        save_jellyfish(counts, &path, 1 << 16).unwrap();
+        let written: String = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(written, fs::read_to_string("fixtures/collapse.k3.jf.txt").unwrap());
+    }
+
+    #[test]
+    fn only_neighbors_with_enough_support_absorb() {
+        let pool: ThreadPool = build_pool(2).unwrap();
+        let forward: HashMap<String, u32> = count_kmers(&collapse_sequences(), 3, &pool, &CountOptions::default()).unwrap();
+        let mut collapsed: HashMap<String, u32> = forward.clone();
+        assert_eq!(collapse_errors(&mut collapsed, DEFAULT_COLLAPSE_RATIO, false, &pool), 0);
+        assert_eq!(collapsed, forward);
+
+        let mut counts: HashMap<String, u32> = HashMap::from([(String::from("ACGT"), 9), (String::from("ACCT"), 1), (String::from("TTTT"), 5)]);
+        assert_eq!(collapse_errors(&mut counts, DEFAULT_COLLAPSE_RATIO, false, &pool), 0);
+        assert_eq!(collapse_errors(&mut counts, 9, false, &pool), 1);
+        assert_eq!(counts, HashMap::from([(String::from("ACGT"), 10), (String::from("TTTT"), 5)]));
+    }
+}
@@ -0,0 +1,740 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use rayon::{ThreadPool, ThreadPoolBuilder, prelude::*};
+
+use crate::alphabet::Alphabet;
+use crate::bloom::BloomFilter;
+use crate::counter::Count;
+use crate::error::KmerError;
+use crate::{info, warn};
+use crate::kmer::{canonical_kmer, canonical_u64, decode_kmer, encode_kmer, reverse_complement};
+use crate::minimizer::{canonical_minimizers, Tiebreak};
+
+// Gap and stop characters of aligned sequences, skipped unless configured otherwise
+pub const DEFAULT_IGNORE_CHARS: &[u8] = b"-*";
+
+// Strand a window is counted on. Reverse counts the reverse complement of every
+// window instead of the window itself, a diagnostic to check strand assumptions
+// (after canonicalization both strands give identical counts)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strand {
+    #[default]
+    Forward,
+    Reverse,
+}
+
+impl Strand {
+    pub fn parse(value: &str) -> Result<Strand, KmerError> {
+        match value {
+            "forward" => Ok(Strand::Forward),
+            "reverse" => Ok(Strand::Reverse),
+            _ => Err(KmerError::Argument(String::from("--strand must be forward or reverse"))),
+        }
+    }
+}
+
+// Settings that change which k-mers end up in the map
+#[derive(Clone, Debug)]
+pub struct CountOptions {
+    // Size in MB of a Bloom filter that keeps first sightings out of the map.
+    // K-mers seen exactly once are then absent from the result by design, every
+    // other k-mer keeps its exact count (up to Bloom filter false positives,
+    // which can let a singleton through or overcount a k-mer by one; which keys they
+    // hit depends on the thread interleaving)
+    pub bloom_megabytes: Option<usize>,
+    // Count each k-mer together with its reverse complement under the lexically smaller form
+    pub canonical: bool,
+    // Once set, no further sequences are started and the k-mers counted so far
+    // are returned, e.g. after Ctrl-C
+    pub stop: Option<Arc<AtomicBool>>,
+    // Like stop, but set by the clock: past this instant no further sequences
+    // are started (--time-limit)
+    pub deadline: Option<Instant>,
+    // Skip windows overlapping soft-masked (lowercase) bases
+    pub skip_masked: bool,
+    // Count only the canonical minimizers of every window of this many k-mers (k <= 32)
+    pub canonical_minimizers: Option<usize>,
+    // Minimizer among equal minimum values of a window
+    pub minimizer_tiebreak: Tiebreak,
+    // Characters that exclude every window they are part of
+    pub ignore_chars: Vec<u8>,
+    // Fail instead of saturating (with a warning) when a counter overflows
+    pub strict: bool,
+    // Strand the windows are counted on, see Strand
+    pub strand: Strand,
+    // Custom alphabet that replaces the A/C/G/T complement rules and excludes
+    // windows with characters outside of it
+    pub alphabet: Option<Alphabet>,
+    // Replace the middle base of every (odd-length) window with WILDCARD, which
+    // merges the four variants that differ only there
+    pub central_wildcard: bool,
+    // Sequences between two progress lines, None: every 10% of the input, 0: none
+    pub progress_every: Option<usize>,
+}
+
+impl Default for CountOptions {
+    fn default() -> CountOptions {
+        CountOptions {
+            bloom_megabytes: None,
+            canonical: false,
+            stop: None,
+            deadline: None,
+            skip_masked: false,
+            canonical_minimizers: None,
+            minimizer_tiebreak: Tiebreak::Leftmost,
+            ignore_chars: DEFAULT_IGNORE_CHARS.to_vec(),
+            strict: false,
+            strand: Strand::Forward,
+            alphabet: None,
+            central_wildcard: false,
+            progress_every: None,
+        }
+    }
+}
+
+impl CountOptions {
+
+    // Bases that exclude every window they are part of
+    fn skips_base(&self, base: u8) -> bool {
+        (self.skip_masked && base.is_ascii_lowercase())
+            || self.ignore_chars.contains(&base)
+            || self.alphabet.as_ref().is_some_and(|alphabet| !alphabet.contains(base))
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.interrupted() || self.timed_out()
+    }
+
+    // Stopped through the stop flag, e.g. by Ctrl-C
+    pub fn interrupted(&self) -> bool {
+        self.stop.as_ref().is_some_and(|stop| stop.load(Ordering::Relaxed))
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+// Builds a dedicated pool so callers never have to touch rayon's global pool,
+// which can only be initialized once per process
+pub fn build_pool(threads: usize) -> Result<ThreadPool, KmerError> {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|error| KmerError::Io(io::Error::other(format!("Unable to build thread pool: {}", error))))
+}
+
+// Upper bound on the windows handled by one parallel task, longer sequences are
+// split so a single long read neither occupies one thread nor grows one huge local map
+const MAX_TASK_WINDOWS: usize = 1 << 16;
+
+// Bases one thread should have to count before another thread pays for itself
+const BASES_PER_THREAD: usize = 1 << 20;
+
+// Thread count for automatic scaling: one thread per BASES_PER_THREAD bases, at
+// most one per parallel task (a sequence or a MAX_TASK_WINDOWS piece of one) and
+// at most the available cores. Tiny inputs run on a single thread.
+pub fn auto_threads(sequences: usize, bases: usize, available: usize) -> usize {
+    let by_bases: usize = bases / BASES_PER_THREAD;
+    let by_tasks: usize = sequences.max(bases / MAX_TASK_WINDOWS);
+    by_bases.min(by_tasks).min(available).max(1)
+}
+
+// All len - k + 1 windows of length k over a sequence, minus the windows that
+// contain a skipped base. Sequences shorter than k (e.g. after trimming)
+// contribute nothing. Non-ASCII bytes are never part of a window, so every
+// window starts and ends on a character boundary.
+pub(crate) fn kmer_windows<'a>(sequence: &'a str, k: usize, options: &'a CountOptions) -> impl Iterator<Item = &'a str> + 'a {
+
+    let bytes: &[u8] = sequence.as_bytes();
+
+    // Start of the first window that does not contain a skipped base seen so far
+    let mut clean_from: usize = 0;
+
+    (0..bytes.len()).filter_map(move |end| {
+        if !bytes[end].is_ascii() || options.skips_base(bytes[end]) {
+            clean_from = end + 1;
+        }
+        let start: usize = (end + 1).checked_sub(k)?;
+        if start < clean_from {
+            return None;
+        }
+        Some(&sequence[start..end + 1])
+    })
+}
+
+// Cuts sequences into pieces of at most MAX_TASK_WINDOWS windows. Consecutive
+// pieces overlap by k - 1 bases, so every window lies in exactly one piece and
+// the counts equal those of the unsplit sequence. The flag marks the first piece
+// of each sequence, which is the one that reports progress. Minimizer windows
+// span several k-mers, so in minimizer mode sequences are never split.
+pub(crate) fn split_into_tasks<'a>(sequences: &'a [String], k: usize, options: &CountOptions) -> Vec<(bool, &'a str)> {
+
+    let mut tasks: Vec<(bool, &str)> = Vec::with_capacity(sequences.len());
+
+    for sequence in sequences {
+        let windows: usize = if sequence.len() >= k { sequence.len() - k + 1 } else { 0 };
+        // Cutting at byte offsets is only safe on ASCII sequences
+        if windows <= MAX_TASK_WINDOWS || options.canonical_minimizers.is_some() || !sequence.is_ascii() {
+            tasks.push((true, sequence));
+            continue;
+        }
+        let mut start: usize = 0;
+        while start < windows {
+            let end: usize = (start + MAX_TASK_WINDOWS).min(windows);
+            tasks.push((start == 0, &sequence[start..end + k - 1]));
+            start = end;
+        }
+    }
+    tasks
+}
+
+// Placeholder of the central base under central_wildcard
+pub const WILDCARD: char = 'N';
+
+// Key under which a window is counted. The wildcard goes in before the
+// canonical form is taken: the middle base of an odd k-mer stays in the middle
+// on the reverse strand, so both strands still share a key.
+pub(crate) fn kmer_key<'a>(kmer: &'a str, k: usize, options: &CountOptions) -> Cow<'a, str> {
+    if options.central_wildcard {
+        let middle: usize = k / 2;
+        let wildcarded: String = format!("{}{}{}", &kmer[..middle], WILDCARD, &kmer[middle + 1..]);
+        return Cow::Owned(strand_key(&wildcarded, k, options).into_owned());
+    }
+    strand_key(kmer, k, options)
+}
+
+fn strand_key<'a>(kmer: &'a str, k: usize, options: &CountOptions) -> Cow<'a, str> {
+    if let Some(alphabet) = &options.alphabet {
+        if options.canonical {
+            return Cow::Owned(alphabet.canonical(kmer));
+        }
+        return match options.strand {
+            Strand::Forward => Cow::Borrowed(kmer),
+            Strand::Reverse => alphabet.reverse_complement(kmer).map_or(Cow::Borrowed(kmer), Cow::Owned),
+        };
+    }
+    if options.canonical {
+        // Packed fast path for uppercase ACGT k-mers of up to 32 bases. Decoding
+        // gives uppercase, so soft-masked k-mers take canonical_kmer, keeping their case.
+        let uppercase: bool = kmer.bytes().all(|base| matches!(base, b'A' | b'C' | b'G' | b'T'));
+        return match encode_kmer(kmer).filter(|_| uppercase) {
+            Some(encoded) => Cow::Owned(decode_kmer(canonical_u64(encoded, k), k)),
+            None => Cow::Owned(canonical_kmer(kmer)),
+        };
+    }
+    match options.strand {
+        Strand::Forward => Cow::Borrowed(kmer),
+        Strand::Reverse => Cow::Owned(reverse_complement(kmer)),
+    }
+}
+
+// Keys counted for one task: every window, or only the selected minimizers
+pub(crate) fn task_keys<'a>(sequence: &'a str, k: usize, options: &'a CountOptions) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+    match options.canonical_minimizers {
+        // Skipped bases end a minimizer window like they end a k-mer window, so
+        // the minimizers are selected per stretch between them
+        Some(w) => Box::new(
+            sequence.split(move |base: char| !base.is_ascii() || options.skips_base(base as u8))
+                .flat_map(move |stretch| canonical_minimizers(stretch, k, w, options.minimizer_tiebreak))
+                .map(move |(_, value)| Cow::Owned(decode_kmer(value, k)))
+        ),
+        None => Box::new(kmer_windows(sequence, k, options).map(move |kmer| kmer_key(kmer, k, options))),
+    }
+}
+
+// Sequences between two progress lines, 0 if progress is disabled
+fn progress_interval(total: usize, options: &CountOptions) -> usize {
+    options.progress_every.unwrap_or((total / 10).max(1))
+}
+
+// Increments the processed sequences counter and prints every interval
+// sequences, returns whether this call printed
+fn report_progress(progress: &AtomicUsize, interval: usize) -> bool {
+    let total_progress = progress.fetch_add(1, Ordering::Relaxed) + 1;
+    let due: bool = interval > 0 && total_progress.is_multiple_of(interval);
+    if due {
+        info!("{}", total_progress);
+    }
+    due
+}
+
+// Adds to a counter without wrapping around: on overflow the counter stays at the
+// type's maximum and a warning is printed once per run. Under strict the overflow
+// is only recorded, counting stops and overflow_error turns it into an error.
+pub(crate) fn add_count<C: Count>(count: &mut C, value: C, overflowed: &AtomicBool, options: &CountOptions) {
+    match count.checked_add(value) {
+        Some(sum) => *count = sum,
+        None => {
+            if !overflowed.swap(true, Ordering::Relaxed) && !options.strict {
+                warn!("Warning: a k-mer occurs more than {} times, its count is capped at that value (use a wider --count-type)", C::MAX);
+            }
+            *count = C::MAX;
+        }
+    }
+}
+
+// Whether a strict run has to stop because a counter overflowed
+pub(crate) fn overflow_stop(overflowed: &AtomicBool, options: &CountOptions) -> bool {
+    options.strict && overflowed.load(Ordering::Relaxed)
+}
+
+// The error of a strict run whose counters of type C overflowed
+pub(crate) fn overflow_error<C: Count>(overflowed: &AtomicBool, options: &CountOptions) -> Result<(), KmerError> {
+    if overflow_stop(overflowed, options) {
+        return Err(KmerError::Overflow(C::MAX.to_u64()));
+    }
+    Ok(())
+}
+
+// Counts the keys of one sequence (or task) into map, the step underneath every
+// k-mer count. With a Bloom filter only keys it has seen before are counted.
+fn add_keys<C: Count>(map: &mut HashMap<String, C>, sequence: &str, k: usize, options: &CountOptions, bloom: Option<&BloomFilter>, overflowed: &AtomicBool) {
+    for key in task_keys(sequence, k, options) {
+        if let Some(bloom) = bloom {
+            if !bloom.insert(&key) {
+                continue;
+            }
+        }
+        add_count(map.entry(key.into_owned()).or_default(), C::ONE, overflowed, options);
+    }
+}
+
+// Adds the k-mers of one sequence to an existing table in place, applying the
+// same canonical/strand, skip and minimizer rules as count_kmers. Adding the
+// sequences one by one gives the table count_kmers returns for all of them.
+// A Bloom filter needs state across calls, so bloom_megabytes is ignored here.
+pub fn add_sequence<C: Count>(map: &mut HashMap<String, C>, sequence: &[u8], k: usize, options: &CountOptions) -> Result<(), KmerError> {
+    // Invalid UTF-8 becomes replacement characters, non-ASCII is never counted anyway
+    let sequence: Cow<str> = String::from_utf8_lossy(sequence);
+    let overflowed = AtomicBool::new(false);
+    add_keys(map, &sequence, k, options, None, &overflowed);
+    overflow_error::<C>(&overflowed, options)
+}
+
+// Generic over the counter type, see add_count for the overflow behavior
+pub fn count_kmers<C: Count>(sequences: &[String], k: usize, pool: &ThreadPool, options: &CountOptions) -> Result<HashMap<String, C>, KmerError> {
+
+    // Shared HashMap protected by a Mutex for thread-safe updates
+    let kmer_hashmap = Mutex::new(HashMap::new());
+
+    // Optional Bloom filter front-end, only k-mers it has already seen get counted
+    let bloom: Option<BloomFilter> = options.bloom_megabytes.map(BloomFilter::with_megabytes);
+
+    // Set by the first counter overflow, so the warning is printed only once
+    let overflowed = AtomicBool::new(false);
+
+    let interval: usize = progress_interval(sequences.len(), options);
+    if interval > 0 {
+        info!("Processed sequences:");
+    }
+
+    // Atomic counter to track total processed sequences
+    let progress = AtomicUsize::new(0);
+
+    let tasks: Vec<(bool, &str)> = split_into_tasks(sequences, k, options);
+
+    // Process sequences in parallel on the caller's pool
+    pool.install(|| {
+        tasks.par_iter().for_each(|&(first_piece, sequence)| {
+
+            if options.stopped() || overflow_stop(&overflowed, options) {
+                return;
+            }
+
+            // Local HashMap for each thread to reduce contention
+            let mut local_map: HashMap<String, C> = HashMap::new();
+
+            if first_piece {
+                report_progress(&progress, interval);
+            }
+
+            add_keys(&mut local_map, sequence, k, options, bloom.as_ref(), &overflowed);
+
+            // Merge local HashMap into the global one
+            let mut global_map = kmer_hashmap.lock().unwrap();
+            for (key, value) in local_map {
+                add_count(global_map.entry(key).or_default(), value, &overflowed, options);
+            }
+        });
+    });
+
+    let mut kmer_hashmap: HashMap<String, C> = Mutex::into_inner(kmer_hashmap).unwrap();
+
+    // Every counted k-mer also had a first sighting that only went to the Bloom filter
+    if bloom.is_some() {
+        for count in kmer_hashmap.values_mut() {
+            add_count(count, C::ONE, &overflowed, options);
+        }
+    }
+
+    overflow_error::<C>(&overflowed, options)?;
+    Ok(kmer_hashmap)
+}
+
+// Folds strand-specific counts into canonical form after counting: every k-mer
+// adds its count to the lexically smaller of itself and its reverse complement.
+// Gives the same table as counting with options.canonical in the first place.
+pub fn fold_canonical<C: Count>(kmer_hashmap: HashMap<String, C>, k: usize, options: &CountOptions) -> Result<HashMap<String, C>, KmerError> {
+
+    let canonical = CountOptions { canonical: true, ..options.clone() };
+    let overflowed = AtomicBool::new(false);
+    let mut folded: HashMap<String, C> = HashMap::with_capacity(kmer_hashmap.len());
+
+    for (key, value) in kmer_hashmap {
+        let canonical_key: String = kmer_key(&key, k, &canonical).into_owned();
+        add_count(folded.entry(canonical_key).or_default(), value, &overflowed, options);
+    }
+    overflow_error::<C>(&overflowed, options)?;
+    Ok(folded)
+}
+
+// Presence-only variant of count_kmers that skips the counters entirely
+pub fn count_kmer_set(sequences: &[String], k: usize, pool: &ThreadPool, options: &CountOptions) -> HashSet<String> {
+
+    // Shared HashSet protected by a Mutex for thread-safe updates
+    let kmer_set = Mutex::new(HashSet::new());
+
+    // With a Bloom filter only k-mers seen at least twice make it into the set
+    let bloom: Option<BloomFilter> = options.bloom_megabytes.map(BloomFilter::with_megabytes);
+
+    let interval: usize = progress_interval(sequences.len(), options);
+    if interval > 0 {
+        info!("Processed sequences:");
+    }
+
+    let progress = AtomicUsize::new(0);
+
+    let tasks: Vec<(bool, &str)> = split_into_tasks(sequences, k, options);
+
+    pool.install(|| {
+        tasks.par_iter().for_each(|&(first_piece, sequence)| {
+
+            if options.stopped() {
+                return;
+            }
+
+            let mut local_set: HashSet<String> = HashSet::new();
+
+            if first_piece {
+                report_progress(&progress, interval);
+            }
+
+            for key in task_keys(sequence, k, options) {
+                if let Some(bloom) = &bloom {
+                    if !bloom.insert(&key) {
+                        continue;
+                    }
+                }
+                if !local_set.contains(key.as_ref()) {
+                    local_set.insert(key.into_owned());
+                }
+            }
+
+            kmer_set.lock().unwrap().extend(local_set);
+        });
+    });
+
+    Mutex::into_inner(kmer_set).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequences::{get_records, ReadOptions};
+
+    #[test]
+    fn canonical_key_keeps_soft_masking() {
+        let options: CountOptions = CountOptions { canonical: true, ..CountOptions::default() };
+        assert_eq!(strand_key("acg", 3, &options), "acg");
+        assert_eq!(strand_key("tgc", 3, &options), "gca");
+        assert_eq!(strand_key("TGC", 3, &options), "GCA");
+    }
+
+    #[test]
+    fn u16_counts_saturate() {
+        let pool: ThreadPool = build_pool(2).unwrap();
+        let sequences: Vec<String> = vec!["A".repeat(70_000), "A".repeat(100)];
+        let counts: HashMap<String, u16> = count_kmers(&sequences, 1, &pool, &CountOptions::default()).unwrap();
+        assert_eq!(counts["A"], u16::MAX);
+
+        let strict: CountOptions = CountOptions { strict: true, ..CountOptions::default() };
+        assert!(matches!(count_kmers::<u16>(&sequences, 1, &pool, &strict), Err(KmerError::Overflow(65535))));
+        let counts: HashMap<String, u32> = count_kmers(&sequences, 1, &pool, &strict).unwrap();
+        assert_eq!(counts["A"], 70_100);
+    }
+
+    #[test]
+    fn u64_counts_beyond_u32() {
+        let overflowed = AtomicBool::new(false);
+        let mut count: u64 = u32::MAX as u64;
+        add_count(&mut count, 2, &overflowed, &CountOptions::default());
+        assert_eq!(count, 4_294_967_297);
+        assert!(!overflowed.load(Ordering::Relaxed));
+    }
+
+    fn minimizer_keys(sequence: &str, options: &CountOptions) -> Vec<String> {
+        task_keys(sequence, 3, options).map(Cow::into_owned).collect()
+    }
+
+    #[test]
+    fn minimizers_respect_skipped_bases() {
+        let plain: CountOptions = CountOptions { canonical_minimizers: Some(2), ..CountOptions::default() };
+        let masked: CountOptions = CountOptions { skip_masked: true, ..plain.clone() };
+        let stretches: Vec<String> = [minimizer_keys("ACGT", &plain), minimizer_keys("ACGTACGT", &plain)].concat();
+
+        assert_ne!(minimizer_keys("ACGTacgtACGTACGT", &plain), minimizer_keys("ACGTacgtACGTACGT", &masked));
+        assert_eq!(minimizer_keys("ACGTacgtACGTACGT", &masked), stretches);
+        assert_eq!(minimizer_keys("ACGT*ACGTACGT", &plain), stretches);
+    }
+
+    // With a filter far larger than the key set there are no false positives
+    #[test]
+    fn bloom_drops_singletons_only() {
+        let sequences: Vec<String> = vec![String::from("AAAAC"), String::from("AAAGT"), String::from("GGG")];
+        let options: CountOptions = CountOptions { bloom_megabytes: Some(1), ..CountOptions::default() };
+        for threads in [1, 4] {
+            let counts: HashMap<String, u32> = count_kmers(&sequences, 3, &build_pool(threads).unwrap(), &options).unwrap();
+            assert_eq!(counts, HashMap::from([(String::from("AAA"), 3)]));
+        }
+
+        let sequences: Vec<String> = vec![String::from("ACGACGACG"), String::from("TTTT")];
+        let exact: HashMap<String, u32> = count_kmers(&sequences, 3, &build_pool(1).unwrap(), &CountOptions::default()).unwrap();
+        let filtered: HashMap<String, u32> = count_kmers(&sequences, 3, &build_pool(2).unwrap(), &options).unwrap();
+        let repeated: HashMap<String, u32> = exact.into_iter().filter(|&(_, count)| count >= 2).collect();
+        assert_eq!(filtered, repeated);
+    }
+
+    #[test]
+    fn count_kmers_reuses_pool() {
+        let pool: ThreadPool = build_pool(2).unwrap();
+        let sequences: Vec<String> = vec![String::from("ACGTA"), String::from("CGT")];
+        for _ in 0..2 {
+            let counts: HashMap<String, u32> = count_kmers(&sequences, 3, &pool, &CountOptions::default()).unwrap();
+            assert_eq!(counts.len(), 3);
+            assert_eq!(counts["ACG"], 1);
+            assert_eq!(counts["CGT"], 2);
+            assert_eq!(counts["GTA"], 1);
+        }
+    }
+
+    // A 300 kb read is cut into MAX_TASK_WINDOWS pieces overlapping by k - 1
+    // bases: every window is counted once, on any number of threads
+    #[test]
+    fn long_read_counts_every_window_once() {
+        let read: String = crate::selftest::random_sequences(1, 600_000, 7).remove(0);
+        assert!(read.len() >= 300_000);
+        let k: usize = 21;
+        let sequences: Vec<String> = vec![read];
+        let options: CountOptions = CountOptions::default();
+
+        let tasks: Vec<(bool, &str)> = split_into_tasks(&sequences, k, &options);
+        assert!(tasks.len() > 4);
+        assert_eq!(tasks.iter().filter(|(first, _)| *first).count(), 1);
+        assert_eq!(tasks.iter().map(|(_, piece)| piece.len() - k + 1).sum::<usize>(), sequences[0].len() - k + 1);
+
+        let mut expected: HashMap<String, u32> = HashMap::new();
+        for start in 0..=sequences[0].len() - k {
+            *expected.entry(sequences[0][start..start + k].to_string()).or_default() += 1;
+        }
+        for threads in [1, 3, 8] {
+            let counts: HashMap<String, u32> = count_kmers(&sequences, k, &build_pool(threads).unwrap(), &options).unwrap();
+            assert_eq!(counts.values().map(|&count| count as usize).sum::<usize>(), sequences[0].len() - k + 1);
+            assert_eq!(counts, expected);
+        }
+    }
+
+
+    // Windows starting at 2 to 6 overlap the masked acg and are not counted
+    #[test]
+    fn masked_windows_are_skipped() {
+        let sequences: Vec<String> = vec![String::from("ACGTacgTACGT")];
+        let pool: ThreadPool = build_pool(2).unwrap();
+        let options: CountOptions = CountOptions { skip_masked: true, ..CountOptions::default() };
+        let counts: HashMap<String, u32> = count_kmers(&sequences, 3, &pool, &options).unwrap();
+        assert_eq!(counts, HashMap::from([(String::from("ACG"), 2), (String::from("CGT"), 2), (String::from("TAC"), 1)]));
+
+        let mut added: HashMap<String, u32> = HashMap::new();
+        add_sequence(&mut added, sequences[0].as_bytes(), 3, &options).unwrap();
+        assert_eq!(added, counts);
+
+        // Without the option the masked windows count under their own case
+        let unmasked: HashMap<String, u32> = count_kmers(&sequences, 3, &pool, &CountOptions::default()).unwrap();
+        assert_eq!(unmasked.values().sum::<u32>(), 10);
+        assert_eq!(unmasked.get("acg"), Some(&1));
+    }
+
+
+    // Windows over the gap and stop characters are skipped by default, with an
+    // empty ignore set they are counted like bases
+    #[test]
+    fn gap_spanning_windows_are_excluded() {
+        let sequences: Vec<String> = vec![String::from("ACG-TAC*GT")];
+        let pool: ThreadPool = build_pool(2).unwrap();
+        let counts: HashMap<String, u32> = count_kmers(&sequences, 3, &pool, &CountOptions::default()).unwrap();
+        assert_eq!(counts, HashMap::from([(String::from("ACG"), 1), (String::from("TAC"), 1)]));
+
+        let kept: CountOptions = CountOptions { ignore_chars: Vec::new(), ..CountOptions::default() };
+        let counts: HashMap<String, u32> = count_kmers(&sequences, 3, &pool, &kept).unwrap();
+        assert_eq!(counts.values().sum::<u32>(), 8);
+        assert_eq!(counts.get("G-T"), Some(&1));
+
+        let gaps_only: CountOptions = CountOptions { ignore_chars: b"-".to_vec(), ..CountOptions::default() };
+        let counts: HashMap<String, u32> = count_kmers(&sequences, 3, &pool, &gaps_only).unwrap();
+        assert_eq!(counts.get("AC*"), Some(&1));
+        assert!(!counts.keys().any(|kmer| kmer.contains('-')));
+    }
+
+
+    // Driven past u32::MAX a count stays at the maximum instead of wrapping,
+    // the flag behind the one-time warning (or the strict error) is set
+    #[test]
+    fn u32_counts_saturate_instead_of_wrapping() {
+        let overflowed = AtomicBool::new(false);
+        let mut count: u32 = u32::MAX - 1;
+        add_count(&mut count, 1, &overflowed, &CountOptions::default());
+        assert_eq!(count, u32::MAX);
+        assert!(!overflowed.load(Ordering::Relaxed));
+        for _ in 0..3 {
+            add_count(&mut count, 1, &overflowed, &CountOptions::default());
+        }
+        assert_eq!(count, u32::MAX);
+        assert!(overflowed.load(Ordering::Relaxed));
+        assert!(overflow_error::<u32>(&overflowed, &CountOptions::default()).is_ok());
+        let strict: CountOptions = CountOptions { strict: true, ..CountOptions::default() };
+        assert!(matches!(overflow_error::<u32>(&overflowed, &strict), Err(KmerError::Overflow(4_294_967_295))));
+
+        // The same for a table grown in place
+        let mut map: HashMap<String, u32> = HashMap::from([(String::from("ACG"), u32::MAX - 1)]);
+        add_sequence(&mut map, b"ACGACG", 3, &CountOptions::default()).unwrap();
+        assert_eq!(map["ACG"], u32::MAX);
+        assert_eq!(map["CGA"], 1);
+        assert!(matches!(add_sequence(&mut map, b"ACG", 3, &strict), Err(KmerError::Overflow(_))));
+    }
+
+    // Reverse counts the reverse complement of every window: the forward counts of
+    // the reads are the reverse counts of their reverse complements
+    #[test]
+    fn reverse_strand_mirrors_the_reverse_complement() {
+        let pool: ThreadPool = build_pool(2).unwrap();
+        let sequences: Vec<String> = vec![String::from("AACGTTTGCA"), String::from("acgGATC")];
+        let complements: Vec<String> = sequences.iter().map(|sequence| reverse_complement(sequence)).collect();
+        let reverse: CountOptions = CountOptions { strand: Strand::Reverse, ..CountOptions::default() };
+
+        let forward_counts: HashMap<String, u32> = count_kmers(&sequences, 3, &pool, &CountOptions::default()).unwrap();
+        let reverse_counts: HashMap<String, u32> = count_kmers(&complements, 3, &pool, &reverse).unwrap();
+        assert_eq!(forward_counts, reverse_counts);
+        assert_ne!(forward_counts, count_kmers::<u32>(&sequences, 3, &pool, &reverse).unwrap());
+
+        let canonical: CountOptions = CountOptions { canonical: true, ..CountOptions::default() };
+        assert_eq!(count_kmers::<u32>(&sequences, 3, &pool, &canonical).unwrap(),
+            count_kmers::<u32>(&sequences, 3, &pool, &CountOptions { strand: Strand::Reverse, ..canonical.clone() }).unwrap());
+        assert!(Strand::parse("both").is_err());
+    }
+
+    // Strand-specific counts of either strand, folded afterwards, are the canonical counts
+    #[test]
+    fn folded_strand_counts_equal_canonical_counts() {
+        let sequences: Vec<String> = get_records(&"fixtures/tiny.fq".to_string(), &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect();
+        let pool: ThreadPool = build_pool(2).unwrap();
+        let canonical: HashMap<String, u32> = count_kmers(&sequences, 5, &pool, &CountOptions { canonical: true, ..CountOptions::default() }).unwrap();
+
+        for strand in [Strand::Forward, Strand::Reverse] {
+            let options: CountOptions = CountOptions { strand, ..CountOptions::default() };
+            let stranded: HashMap<String, u32> = count_kmers(&sequences, 5, &pool, &options).unwrap();
+            assert!(stranded.len() > canonical.len());
+            assert_eq!(fold_canonical(stranded, 5, &options).unwrap(), canonical);
+        }
+    }
+
+    // Two sequences of ten bases run on one thread, large inputs use every core
+    #[test]
+    fn threads_scale_with_the_input() {
+        let sequences: Vec<String> = get_records(&"fixtures/short.fa".to_string(), &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect();
+        let bases: usize = sequences.iter().map(String::len).sum();
+        assert_eq!(auto_threads(sequences.len(), bases, 16), 1);
+
+        // A few long sequences are split into pieces, many short ones run in parallel as they are
+        assert_eq!(auto_threads(2, 64 * BASES_PER_THREAD, 16), 16);
+        assert_eq!(auto_threads(100_000, 8 * BASES_PER_THREAD, 16), 8);
+        assert_eq!(auto_threads(1_000_000, 1_000 * BASES_PER_THREAD, 16), 16);
+        assert_eq!(auto_threads(1_000_000, 1_000 * BASES_PER_THREAD, 1), 1);
+    }
+
+    // AAA, ACA, AGA and ATA of the fixture share ANA, canonically together with
+    // the three TNT windows of the reverse strand
+    #[test]
+    fn central_variants_share_a_key() {
+        let sequences: Vec<String> = get_records(&"fixtures/central_wildcard.fa".to_string(), &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect();
+        let pool: ThreadPool = build_pool(2).unwrap();
+        let options: CountOptions = CountOptions { central_wildcard: true, ..CountOptions::default() };
+
+        let counts: HashMap<String, u32> = count_kmers(&sequences, 3, &pool, &options).unwrap();
+        assert_eq!(counts["ANA"], 4);
+        assert_eq!(counts["TNT"], 3);
+        assert!(counts.keys().all(|kmer| kmer.as_bytes()[1] == WILDCARD as u8));
+        assert_eq!(counts.values().sum::<u32>(), 17);
+
+        let canonical: HashMap<String, u32> = count_kmers(&sequences, 3, &pool, &CountOptions { canonical: true, ..options }).unwrap();
+        assert_eq!(canonical["ANA"], 7);
+        assert!(!canonical.contains_key("TNT"));
+    }
+
+    #[test]
+    fn progress_follows_the_interval() {
+        let reported = |interval: usize| -> Vec<usize> {
+            let progress = AtomicUsize::new(0);
+            (1..=10).filter(|_| report_progress(&progress, interval)).collect()
+        };
+        assert_eq!(reported(3), [3, 6, 9]);
+        assert_eq!(reported(1).len(), 10);
+        assert!(reported(0).is_empty());
+
+        // Every 10% by default, also for fewer than ten sequences
+        assert_eq!(progress_interval(1000, &CountOptions::default()), 100);
+        assert_eq!(progress_interval(0, &CountOptions::default()), 1);
+        assert_eq!(progress_interval(1000, &CountOptions { progress_every: Some(0), ..CountOptions::default() }), 0);
+    }
+
+    // Past the deadline no further sequence is started, what was counted is a
+    // valid subset of the full table
+    #[test]
+    fn time_limit_leaves_a_partial_table() {
+        let sequences: Vec<String> = crate::selftest::random_sequences(400, 1000, 5);
+        let pool: ThreadPool = build_pool(2).unwrap();
+        let full: HashMap<String, u32> = count_kmers(&sequences, 11, &pool, &CountOptions::default()).unwrap();
+
+        let options: CountOptions = CountOptions { deadline: Some(Instant::now() + std::time::Duration::from_millis(2)), ..CountOptions::default() };
+        let partial: HashMap<String, u32> = count_kmers(&sequences, 11, &pool, &options).unwrap();
+        assert!(options.timed_out() && options.stopped() && !options.interrupted());
+        assert!(partial.iter().all(|(kmer, count)| full.get(kmer).is_some_and(|full| count <= full)));
+
+        // Expired before counting, nothing is started
+        assert!(count_kmers::<u32>(&sequences, 11, &pool, &options).unwrap().is_empty());
+    }
+
+    // Growing a table one read at a time gives the batch count, for every set of rules
+    #[test]
+    fn incremental_counts_equal_the_batch_count() {
+        let sequences: Vec<String> = get_records(&"fixtures/tiny.fq".to_string(), &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect();
+        let pool: ThreadPool = build_pool(4).unwrap();
+        let rules: [CountOptions; 4] = [
+            CountOptions::default(),
+            CountOptions { canonical: true, ..CountOptions::default() },
+            CountOptions { strand: Strand::Reverse, skip_masked: true, ..CountOptions::default() },
+            CountOptions { canonical_minimizers: Some(4), ..CountOptions::default() },
+        ];
+        for options in rules {
+            let mut added: HashMap<String, u64> = HashMap::new();
+            for sequence in &sequences {
+                add_sequence(&mut added, sequence.as_bytes(), 7, &options).unwrap();
+            }
+            assert_eq!(added, count_kmers(&sequences, 7, &pool, &options).unwrap());
+        }
+    }
+
+}
@@ -0,0 +1,64 @@
+use std::fmt::Display;
+
+use crate::error::KmerError;
+
+// Integer type the counts are stored in. Smaller types save memory on data with
+// low multiplicities, u64 never overflows in practice.
+pub trait Count: Copy + Default + Ord + Display + Send + Sync + 'static {
+    const ONE: Self;
+    const MAX: Self;
+    fn checked_add(self, other: Self) -> Option<Self>;
+    fn to_u64(self) -> u64;
+    // None if the value does not fit the type
+    fn from_u64(value: u64) -> Option<Self>;
+}
+
+macro_rules! impl_count {
+    ($($ty:ty),*) => {
+        $(
+            impl Count for $ty {
+                const ONE: $ty = 1;
+                const MAX: $ty = <$ty>::MAX;
+                fn checked_add(self, other: $ty) -> Option<$ty> {
+                    <$ty>::checked_add(self, other)
+                }
+                fn to_u64(self) -> u64 {
+                    self as u64
+                }
+                fn from_u64(value: u64) -> Option<$ty> {
+                    <$ty>::try_from(value).ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_count!(u16, u32, u64);
+
+// Counter type selected on the command line
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CountType {
+    U16,
+    #[default]
+    U32,
+    U64,
+}
+
+impl CountType {
+    pub fn parse(value: &str) -> Result<CountType, KmerError> {
+        match value {
+            "u16" => Ok(CountType::U16),
+            "u32" => Ok(CountType::U32),
+            "u64" => Ok(CountType::U64),
+            _ => Err(KmerError::Argument(String::from("--count-type must be u16, u32 or u64"))),
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        match self {
+            CountType::U16 => 2,
+            CountType::U32 => 4,
+            CountType::U64 => 8,
+        }
+    }
+}
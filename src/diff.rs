@@ -0,0 +1,108 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// Per k-mer comparison of two count tables
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiffRow {
+    pub kmer: String,
+    pub count_a: u64,
+    pub count_b: u64,
+    // count_b - count_a
+    pub difference: i128,
+    // count_b / count_a, infinite when the k-mer is missing from the first table
+    pub ratio: f64,
+}
+
+// Thresholds of the reported rows: a row is reported if it reaches at least
+// one of the given thresholds, None disables a check
+#[derive(Clone, Debug, Default)]
+pub struct DiffFilter {
+    pub min_abs_change: Option<u64>,
+    pub min_fold_change: Option<f64>,
+}
+
+impl DiffRow {
+
+    fn fold_change(&self) -> f64 {
+        if self.ratio >= 1.0 { self.ratio } else { 1.0 / self.ratio }
+    }
+
+    fn passes(&self, filter: &DiffFilter) -> bool {
+        let absolute: Option<bool> = filter.min_abs_change.map(|min_abs_change| self.difference.unsigned_abs() >= min_abs_change as u128);
+        let fold: Option<bool> = filter.min_fold_change.map(|min_fold_change| self.fold_change() >= min_fold_change);
+        match (absolute, fold) {
+            (None, None) => true,
+            _ => absolute == Some(true) || fold == Some(true),
+        }
+    }
+}
+
+// Compares two tables row by row, sorted by k-mer. K-mers found in only one
+// table are treated as having count 0 in the other.
+pub fn diff_counts(a: &HashMap<String, u64>, b: &HashMap<String, u64>, filter: &DiffFilter) -> Vec<DiffRow> {
+
+    let kmers: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+
+    kmers.into_iter()
+        .map(|kmer| {
+            let count_a: u64 = a.get(kmer).copied().unwrap_or(0);
+            let count_b: u64 = b.get(kmer).copied().unwrap_or(0);
+            DiffRow {
+                kmer: kmer.clone(),
+                count_a,
+                count_b,
+                difference: count_b as i128 - count_a as i128,
+                ratio: count_b as f64 / count_a as f64,
+            }
+        })
+        .filter(|row| row.passes(filter))
+        .collect()
+}
+
+pub fn save_diff(rows: &[DiffRow], path: &str) -> io::Result<()> {
+
+    let mut file = BufWriter::new(File::create(path)?);
+
+    writeln!(file, "kmer\tcount_a\tcount_b\tdifference\tratio")?;
+    for row in rows {
+        writeln!(file, "{}\t{}\t{}\t{}\t{}", row.kmer, row.count_a, row.count_b, row.difference, row.ratio)?;
+    }
+
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::load_counts;
+
+    fn fixtures() -> (HashMap<String, u64>, HashMap<String, u64>) {
+        (load_counts("fixtures/merge_a.tsv").unwrap(), load_counts("fixtures/merge_b.tsv").unwrap())
+    }
+
+    #[test]
+    fn missing_kmers_count_as_zero() {
+        let (a, b) = fixtures();
+        let rows: Vec<DiffRow> = diff_counts(&a, &b, &DiffFilter::default());
+        let kmers: Vec<&str> = rows.iter().map(|row| row.kmer.as_str()).collect();
+        assert_eq!(kmers, ["AAA", "ACG", "CCC"]);
+
+        assert_eq!((rows[0].count_a, rows[0].count_b, rows[0].difference), (3, 2, -1));
+        assert_eq!((rows[1].count_a, rows[1].count_b, rows[1].difference, rows[1].ratio), (1, 0, -1, 0.0));
+        assert_eq!((rows[2].count_a, rows[2].count_b, rows[2].difference), (0, 5, 5));
+        assert!(rows[2].ratio.is_infinite());
+    }
+
+    // Either threshold is enough to report a row
+    #[test]
+    fn thresholds_are_alternatives() {
+        let (a, b) = fixtures();
+        let kmers = |filter: DiffFilter| -> Vec<String> { diff_counts(&a, &b, &filter).into_iter().map(|row| row.kmer).collect() };
+
+        assert_eq!(kmers(DiffFilter { min_abs_change: Some(2), min_fold_change: None }), ["CCC"]);
+        assert_eq!(kmers(DiffFilter { min_abs_change: None, min_fold_change: Some(1.5) }), ["AAA", "ACG", "CCC"]);
+        assert_eq!(kmers(DiffFilter { min_abs_change: Some(2), min_fold_change: Some(2.0) }), ["ACG", "CCC"]);
+        assert!(kmers(DiffFilter { min_abs_change: Some(10), min_fold_change: Some(1.6) }).iter().all(|kmer| kmer != "AAA"));
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+use std::io;
+
+use crate::sequences::Format;
+
+#[derive(Debug)]
+pub enum KmerError {
+    Io(io::Error),
+    // Malformed input, with the 1-based line number where parsing failed
+    Parse { line: usize, message: String },
+    // Neither extension nor content identify the file as FASTA or FASTQ
+    UnknownFormat(String),
+    // The extension promises one format but the content is the other
+    FormatMismatch { file: String, extension: Format, content: Format },
+    // Corrupt gzip/BGZF data or a malformed BAM file
+    Decompress(String),
+    // Invalid command line
+    Argument(String),
+    // Two FASTA records share an id, an error under --strict
+    DuplicateId(String),
+    // Nothing to count under --strict, e.g. no sequence as long as k
+    Empty(String),
+    // A counter exceeded the maximum of its type under --strict
+    Overflow(u64),
+    // An invariant of the selftest subcommand does not hold
+    Selftest(String),
+    // Any of the above while reading one input file
+    Input { file: String, error: Box<KmerError> },
+}
+
+// Exit codes of the kmeRS binary, documented in the README. Panics are bugs
+// and keep Rust's 101.
+pub const EXIT_IO: i32 = 1;
+pub const EXIT_ARGUMENT: i32 = 2;
+pub const EXIT_INPUT: i32 = 3;
+pub const EXIT_EMPTY: i32 = 4;
+pub const EXIT_SELFTEST: i32 = 5;
+pub const EXIT_OVERFLOW: i32 = 6;
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+impl KmerError {
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            // Content that does not parse, e.g. a malformed BED or alphabet file
+            KmerError::Io(error) if error.kind() == io::ErrorKind::InvalidData => EXIT_INPUT,
+            KmerError::Io(_) => EXIT_IO,
+            KmerError::Parse { .. } | KmerError::UnknownFormat(_) | KmerError::FormatMismatch { .. } | KmerError::Decompress(_) | KmerError::DuplicateId(_) => EXIT_INPUT,
+            KmerError::Argument(_) => EXIT_ARGUMENT,
+            KmerError::Empty(_) => EXIT_EMPTY,
+            KmerError::Selftest(_) => EXIT_SELFTEST,
+            KmerError::Overflow(_) => EXIT_OVERFLOW,
+            KmerError::Input { error, .. } => error.exit_code(),
+        }
+    }
+}
+
+impl fmt::Display for KmerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KmerError::Io(error) => write!(f, "I/O error: {}", error),
+            KmerError::Parse { line, message } => write!(f, "Parse error on line {}: {}", line, message),
+            KmerError::UnknownFormat(file) => write!(f, "Unable to recognize {} as FASTA or FASTQ (use --format to set it)", file),
+            KmerError::FormatMismatch { file, extension, content } => {
+                write!(f, "{} looks like {} by its extension but contains {}", file, extension, content)
+            }
+            KmerError::Decompress(message) => write!(f, "Invalid compressed data: {}", message),
+            KmerError::Argument(message) => write!(f, "{}", message),
+            KmerError::DuplicateId(id) => write!(f, "Duplicate FASTA header >{}", id),
+            KmerError::Empty(message) => write!(f, "Nothing to count: {}", message),
+            KmerError::Overflow(max) => write!(f, "Count overflow: a k-mer occurs more than {} times (use a wider --count-type)", max),
+            KmerError::Selftest(message) => write!(f, "Self-test failed: {}", message),
+            KmerError::Input { file, error } => write!(f, "Unable to read {}: {}", file, error),
+        }
+    }
+}
+
+impl std::error::Error for KmerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KmerError::Io(error) => Some(error),
+            KmerError::Input { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for KmerError {
+    fn from(error: io::Error) -> KmerError {
+        KmerError::Io(error)
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use rayon::{ThreadPool, prelude::*};
+
+use crate::count::{split_into_tasks, task_keys, CountOptions};
+
+// 2^14 registers, the standard error of the estimate is 1.04 / sqrt(2^14) = 0.8%
+const PRECISION: u32 = 14;
+const REGISTERS: usize = 1 << PRECISION;
+
+// Bases parsed for --estimate-memory (16 Mbp, seconds to read), the estimate is
+// extrapolated from this prefix of the input
+pub const ESTIMATE_SAMPLE_BASES: usize = 1 << 24;
+
+// HyperLogLog cardinality sketch, a fixed 16 KB no matter how many k-mers go in
+#[derive(Clone, Debug)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> HyperLogLog {
+        HyperLogLog::new()
+    }
+}
+
+impl HyperLogLog {
+
+    pub fn new() -> HyperLogLog {
+        HyperLogLog { registers: vec![0; REGISTERS] }
+    }
+
+    pub fn insert(&mut self, kmer: &str) {
+        let mut hasher = DefaultHasher::new();
+        kmer.hash(&mut hasher);
+        let hash: u64 = hasher.finish();
+
+        // The first PRECISION bits pick the register, the rest give the rank
+        let register: usize = (hash >> (64 - PRECISION)) as usize;
+        let rank: u8 = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+        self.registers[register] = self.registers[register].max(rank);
+    }
+
+    // Union of two sketches
+    pub fn merge(mut self, other: HyperLogLog) -> HyperLogLog {
+        for (register, other) in self.registers.iter_mut().zip(other.registers) {
+            *register = (*register).max(other);
+        }
+        self
+    }
+
+    pub fn estimate(&self) -> f64 {
+        let m: f64 = REGISTERS as f64;
+        let alpha: f64 = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&rank| 2f64.powi(-(rank as i32))).sum();
+        let estimate: f64 = alpha * m * m / sum;
+
+        // Linear counting is more accurate while many registers are still empty
+        let zeros: usize = self.registers.iter().filter(|&&rank| rank == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            return m * (m / zeros as f64).ln();
+        }
+        estimate
+    }
+}
+
+// Estimated number of distinct k-mers (under the counting keys of options) from a
+// single pass that keeps no per-k-mer state
+pub fn estimate_distinct_kmers(sequences: &[String], k: usize, pool: &ThreadPool, options: &CountOptions) -> f64 {
+
+    let tasks: Vec<(bool, &str)> = split_into_tasks(sequences, k, options);
+
+    let sketch: HyperLogLog = pool.install(|| {
+        tasks.par_iter()
+            .fold(HyperLogLog::new, |mut sketch, &(_, sequence)| {
+                for key in task_keys(sequence, k, options) {
+                    sketch.insert(&key);
+                }
+                sketch
+            })
+            .reduce(HyperLogLog::new, HyperLogLog::merge)
+    });
+    sketch.estimate()
+}
+
+// Bucket array of a hashbrown map with this many (String, C) entries: a power of
+// two of buckets at a load factor of at most 7/8, one control byte per bucket
+fn table_bytes(distinct_kmers: usize, counter_bytes: usize) -> usize {
+    let entry_bytes: usize = (size_of::<String>() + counter_bytes).next_multiple_of(size_of::<usize>());
+    let buckets: usize = (distinct_kmers * 8 / 7 + 1).next_power_of_two();
+    buckets * (entry_bytes + 1)
+}
+
+// Rough size of a HashMap<String, C> with this many k-mers: the bucket array plus
+// one heap allocation of k bytes per key (rounded up to the allocator's 8 bytes)
+pub fn projected_map_bytes(distinct_kmers: usize, k: usize, counter_bytes: usize) -> usize {
+    table_bytes(distinct_kmers, counter_bytes) + distinct_kmers * k.next_multiple_of(8)
+}
+
+// The map's peak is reached while it grows the last time, when the previous
+// bucket array of half the size is still allocated
+pub fn projected_peak_bytes(distinct_kmers: usize, k: usize, counter_bytes: usize) -> usize {
+    projected_map_bytes(distinct_kmers, k, counter_bytes) + table_bytes(distinct_kmers, counter_bytes) / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequences::{sample_records, Record, ReadOptions};
+
+    #[test]
+    fn estimate_is_within_three_percent() {
+        let mut sketch: HyperLogLog = HyperLogLog::new();
+        for value in 0..100_000u32 {
+            sketch.insert(&format!("{:08x}", value));
+        }
+        let error: f64 = (sketch.estimate() - 100_000.0).abs() / 100_000.0;
+        assert!(error < 0.03, "relative error {}", error);
+    }
+
+    #[test]
+    fn sample_is_a_prefix_of_the_input() {
+        let files: Vec<String> = vec!["fixtures/tiny.fq".to_string(), "fixtures/tiny.fq.gz".to_string()];
+        let options: ReadOptions = ReadOptions::default();
+
+        let (all, fraction) = sample_records(&files, &options, usize::MAX).unwrap();
+        assert_eq!(fraction, 1.0);
+        assert_eq!(all.len(), 2);
+
+        // 100 bases end in the first file, the second one is not opened
+        let (prefix, fraction) = sample_records(&files, &options, 100).unwrap();
+        assert_eq!(prefix.len(), 1);
+        let bases: usize = prefix[0].iter().map(|record| record.sequence.len()).sum();
+        assert!((100..150).contains(&bases), "{} bases", bases);
+        let ids: Vec<&str> = prefix[0].iter().map(|record| record.id.as_str()).collect();
+        let expected: Vec<&str> = all[0].iter().take(ids.len()).map(|record: &Record| record.id.as_str()).collect();
+        assert_eq!(ids, expected);
+        assert!(fraction > 0.0 && fraction < 0.25, "fraction {}", fraction);
+
+        // The budget runs out within the compressed file
+        let first: usize = all[0].iter().map(|record| record.sequence.len()).sum();
+        let (prefix, fraction) = sample_records(&files, &options, first + 100).unwrap();
+        assert_eq!(prefix.len(), 2);
+        assert!(prefix[1].len() < all[1].len());
+        assert!(fraction > 0.5 && fraction < 0.75, "fraction {}", fraction);
+    }
+}
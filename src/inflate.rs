@@ -0,0 +1,345 @@
+use crate::error::KmerError;
+
+// Minimal DEFLATE (RFC 1951) and gzip (RFC 1952) decoder, enough for gzip files
+// and the BGZF blocks of BAM files without an external compression crate.
+// Huffman codes are decoded bit by bit as in zlib's reference decoder "puff":
+// slow compared to table-driven decoders, but short and easy to check.
+
+const MAX_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+// Order in which the code length code lengths of a dynamic block are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn corrupt(message: &str) -> KmerError {
+    KmerError::Decompress(message.to_string())
+}
+
+// Reads bits least significant first, as DEFLATE packs them
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    available: u32,
+}
+
+impl<'a> BitReader<'a> {
+
+    fn bits(&mut self, count: u32) -> Result<u32, KmerError> {
+        while self.available < count {
+            let byte: u8 = *self.data.get(self.position).ok_or_else(|| corrupt("unexpected end of compressed data"))?;
+            self.position += 1;
+            self.buffer |= (byte as u32) << self.available;
+            self.available += 8;
+        }
+        let value: u32 = self.buffer & ((1u32 << count) - 1);
+        self.buffer = self.buffer.checked_shr(count).unwrap_or(0);
+        self.available -= count;
+        Ok(value)
+    }
+
+    // Stored blocks and the gzip trailer start on a byte boundary
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.available = 0;
+    }
+}
+
+// Canonical Huffman code: number of codes per length and the symbols ordered by code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+
+    fn new(lengths: &[u8]) -> Result<Huffman, KmerError> {
+        let mut counts: [u16; MAX_BITS + 1] = [0; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+
+        // Over-subscribed codes cannot be decoded, incomplete ones are allowed
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(corrupt("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets: [u16; MAX_BITS + 2] = [0; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols: Vec<u16> = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        counts[0] = 0;
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, KmerError> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count: i32 = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(corrupt("invalid Huffman code"))
+    }
+}
+
+fn fixed_codes() -> Result<(Huffman, Huffman), KmerError> {
+    let mut lengths: [u8; 288] = [8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), KmerError> {
+
+    let literals: usize = reader.bits(5)? as usize + 257;
+    let distances: usize = reader.bits(5)? as usize + 1;
+    let code_lengths: usize = reader.bits(4)? as usize + 4;
+    if literals > 286 || distances > 30 {
+        return Err(corrupt("too many length or distance codes"));
+    }
+
+    let mut lengths: [u8; 19] = [0; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[index] = reader.bits(3)? as u8;
+    }
+    let length_code: Huffman = Huffman::new(&lengths)?;
+
+    // Literal/length and distance code lengths share one run-length coded sequence
+    let mut lengths: Vec<u8> = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let symbol: u16 = length_code.decode(reader)?;
+        let (value, repeat): (u8, u32) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or_else(|| corrupt("repeat without a previous length"))?, 3 + reader.bits(2)?),
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        if lengths.len() + repeat as usize > literals + distances {
+            return Err(corrupt("too many code lengths"));
+        }
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths[256] == 0 {
+        return Err(corrupt("missing end-of-block code"));
+    }
+
+    Ok((Huffman::new(&lengths[..literals])?, Huffman::new(&lengths[literals..])?))
+}
+
+fn inflate_codes(reader: &mut BitReader, output: &mut Vec<u8>, literal: &Huffman, distance: &Huffman) -> Result<(), KmerError> {
+    loop {
+        let symbol: usize = literal.decode(reader)? as usize;
+        if symbol < 256 {
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let symbol: usize = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(corrupt("invalid length code"));
+        }
+        let length: usize = LENGTH_BASE[symbol] as usize + reader.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+        let symbol: usize = distance.decode(reader)? as usize;
+        if symbol >= DISTANCE_BASE.len() {
+            return Err(corrupt("invalid distance code"));
+        }
+        let back: usize = DISTANCE_BASE[symbol] as usize + reader.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+        if back > output.len() {
+            return Err(corrupt("distance too far back"));
+        }
+        // Byte by byte, the copied range may overlap the bytes being written
+        let start: usize = output.len() - back;
+        for i in 0..length {
+            output.push(output[start + i]);
+        }
+    }
+}
+
+// Decompresses one raw DEFLATE stream, returns the position after its last byte
+fn inflate_into(data: &[u8], output: &mut Vec<u8>) -> Result<usize, KmerError> {
+
+    let mut reader = BitReader { data, position: 0, buffer: 0, available: 0 };
+
+    loop {
+        let last: bool = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header: &[u8] = data.get(reader.position..reader.position + 4).ok_or_else(|| corrupt("truncated stored block"))?;
+                let length: usize = u16::from_le_bytes([header[0], header[1]]) as usize;
+                if length != !u16::from_le_bytes([header[2], header[3]]) as usize {
+                    return Err(corrupt("stored block length check failed"));
+                }
+                let start: usize = reader.position + 4;
+                output.extend_from_slice(data.get(start..start + length).ok_or_else(|| corrupt("truncated stored block"))?);
+                reader.position = start + length;
+            }
+            1 => {
+                let (literal, distance) = fixed_codes()?;
+                inflate_codes(&mut reader, output, &literal, &distance)?;
+            }
+            2 => {
+                let (literal, distance) = dynamic_codes(&mut reader)?;
+                inflate_codes(&mut reader, output, &literal, &distance)?;
+            }
+            _ => return Err(corrupt("invalid block type")),
+        }
+        if last {
+            return Ok(reader.position);
+        }
+    }
+}
+
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, KmerError> {
+    let mut output: Vec<u8> = Vec::new();
+    inflate_into(data, &mut output)?;
+    Ok(output)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = !0;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1f, 0x8b])
+}
+
+// Decompresses a gzip file, including files of several concatenated members
+// such as BGZF. Every member's CRC-32 and length are verified.
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, KmerError> {
+
+    let mut output: Vec<u8> = Vec::new();
+    let mut position: usize = 0;
+
+    while position < data.len() {
+        let member: &[u8] = &data[position..];
+        if member.len() < 18 || !is_gzip(member) || member[2] != 8 {
+            return Err(corrupt("not a gzip member"));
+        }
+        let flags: u8 = member[3];
+        let mut offset: usize = 10;
+
+        // Optional header fields: extra field, file name, comment, header CRC
+        if flags & 0x04 != 0 {
+            let length: usize = u16::from_le_bytes([member[offset], member[offset + 1]]) as usize;
+            offset += 2 + length;
+        }
+        for flag in [0x08, 0x10] {
+            if flags & flag != 0 {
+                let end: usize = member.get(offset..).and_then(|rest| rest.iter().position(|&byte| byte == 0)).ok_or_else(|| corrupt("truncated gzip header"))?;
+                offset += end + 1;
+            }
+        }
+        if flags & 0x02 != 0 {
+            offset += 2;
+        }
+
+        let start: usize = output.len();
+        let compressed: &[u8] = member.get(offset..).ok_or_else(|| corrupt("truncated gzip header"))?;
+        let consumed: usize = inflate_into(compressed, &mut output)?;
+
+        let trailer: &[u8] = compressed.get(consumed..consumed + 8).ok_or_else(|| corrupt("truncated gzip trailer"))?;
+        let crc: u32 = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size: u32 = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32(&output[start..]) || size != (output.len() - start) as u32 {
+            return Err(corrupt("gzip checksum mismatch"));
+        }
+        position += offset + consumed + 8;
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequences::{get_records, ReadOptions};
+    use crate::testing::temp_file;
+
+    // The empty last member every BGZF file ends with
+    const BGZF_EOF: [u8; 28] = [
+        0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43,
+        0x02, 0x00, 0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    // Block type of a raw DEFLATE stream's first block
+    fn first_block_type(gzip: &[u8]) -> u8 {
+        (gzip[10] >> 1) & 3
+    }
+
+    #[test]
+    fn stored_block() {
+        let data: [u8; 8] = [0x01, 0x03, 0x00, 0xfc, 0xff, b'A', b'C', b'G'];
+        assert_eq!(inflate(&data).unwrap(), b"ACG");
+    }
+
+    #[test]
+    fn fixed_huffman_block() {
+        let compressed: &[u8] = include_bytes!("../fixtures/jellyfish.fa.gz");
+        assert_eq!(first_block_type(compressed), 1);
+        assert_eq!(gunzip(compressed).unwrap(), include_bytes!("../fixtures/jellyfish.fa"));
+    }
+
+    #[test]
+    fn dynamic_huffman_block() {
+        let compressed: &[u8] = include_bytes!("../fixtures/tiny.fq.gz");
+        assert_eq!(first_block_type(compressed), 2);
+        assert_eq!(gunzip(compressed).unwrap(), include_bytes!("../fixtures/tiny.fq"));
+    }
+
+    #[test]
+    fn bgzf_eof_block() {
+        assert!(gunzip(&BGZF_EOF).unwrap().is_empty());
+        let bam: &[u8] = include_bytes!("../fixtures/tiny.bam");
+        assert!(bam.ends_with(&BGZF_EOF));
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let compressed: &[u8] = include_bytes!("../fixtures/tiny.fq.gz");
+        assert!(matches!(gunzip(&compressed[..compressed.len() / 2]), Err(KmerError::Decompress(_))));
+
+        let path: String = temp_file("truncated.fq.gz", &compressed[..compressed.len() - 12]);
+        let result: Result<_, KmerError> = get_records(&path, &ReadOptions::default());
+        std::fs::remove_file(&path).unwrap();
+        match result {
+            Err(KmerError::Input { file, error }) => {
+                assert_eq!(file, path);
+                assert!(matches!(*error, KmerError::Decompress(_)));
+            }
+            other => panic!("expected an input error, got {:?}", other.map(|records| records.len())),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+// Flag set by the SIGINT handler, counting stops taking new work once it is set
+static STOP: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+#[cfg(unix)]
+extern "C" {
+    fn signal(signum: i32, handler: usize) -> usize;
+    fn _exit(status: i32) -> !;
+}
+
+#[cfg(unix)]
+const SIGINT: i32 = 2;
+
+// Only touches an atomic, which keeps the handler async-signal-safe. A second
+// Ctrl-C exits immediately without waiting for the partial results.
+#[cfg(unix)]
+extern "C" fn handle_sigint(_signum: i32) {
+    if let Some(stop) = STOP.get() {
+        if stop.swap(true, Ordering::SeqCst) {
+            unsafe { _exit(130) };
+        }
+    }
+}
+
+// Installs the Ctrl-C handler (once per process) and returns the flag it sets
+pub fn install_sigint_handler() -> Arc<AtomicBool> {
+    let stop: Arc<AtomicBool> = STOP.get_or_init(|| Arc::new(AtomicBool::new(false))).clone();
+    #[cfg(unix)]
+    unsafe {
+        signal(SIGINT, handle_sigint as *const () as usize);
+    }
+    stop
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::count::{build_pool, count_kmers, CountOptions};
+    use crate::output::{load_counts, save_kmers, SortOrder, DEFAULT_WRITE_BUFFER};
+    use crate::selftest::random_sequences;
+    use crate::testing::temp_path;
+
+    #[test]
+    fn handler_flag_is_shared() {
+        assert!(Arc::ptr_eq(&install_sigint_handler(), &install_sigint_handler()));
+    }
+
+    // Stopping partway (as Ctrl-C does) leaves counts of whole sequences only,
+    // a subset of the full counts, that are written as a well-formed table
+    #[test]
+    fn stopped_count_writes_partial_results() {
+        let sequences: Vec<String> = random_sequences(400, 1000, 3);
+        let pool = build_pool(2).unwrap();
+        let full: HashMap<String, u32> = count_kmers(&sequences, 11, &pool, &CountOptions::default()).unwrap();
+
+        let stop: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+        let options: CountOptions = CountOptions { stop: Some(Arc::clone(&stop)), ..CountOptions::default() };
+        let stopper = {
+            let stop: Arc<AtomicBool> = Arc::clone(&stop);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(5));
+                stop.store(true, Ordering::SeqCst);
+            })
+        };
+        let partial: HashMap<String, u32> = count_kmers(&sequences, 11, &pool, &options).unwrap();
+        stopper.join().unwrap();
+        assert!(options.interrupted());
+        assert!(partial.iter().all(|(kmer, count)| full.get(kmer).is_some_and(|full| count <= full)));
+
+        let path: String = temp_path("partial.tsv");
+        save_kmers(partial.clone(), &path, DEFAULT_WRITE_BUFFER, SortOrder::Lexical).unwrap();
+        let written: HashMap<String, u64> = load_counts(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, partial.into_iter().map(|(kmer, count)| (kmer, count as u64)).collect());
+
+        // Set before counting, nothing is started
+        assert!(count_kmers::<u32>(&sequences, 11, &pool, &options).unwrap().is_empty());
+    }
+}
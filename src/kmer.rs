@@ -0,0 +1,102 @@
+// 2-bit encoding in lexical order (A < C < G < T), so comparing encoded k-mers
+// gives the same result as comparing the uppercase strings
+pub(crate) fn encode_base(base: u8) -> Option<u64> {
+    match base {
+        b'A' | b'a' => Some(0),
+        b'C' | b'c' => Some(1),
+        b'G' | b'g' => Some(2),
+        b'T' | b't' => Some(3),
+        _ => None,
+    }
+}
+
+const BASES: [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+// Packs a k-mer of up to 32 bases into a u64, first base in the highest bits.
+// Returns None for longer k-mers or bases other than A/C/G/T.
+pub fn encode_kmer(kmer: &str) -> Option<u64> {
+    if kmer.len() > 32 {
+        return None;
+    }
+    kmer.bytes().try_fold(0u64, |encoded, base| Some((encoded << 2) | encode_base(base)?))
+}
+
+pub fn decode_kmer(encoded: u64, k: usize) -> String {
+    (0..k)
+        .map(|i| BASES[((encoded >> (2 * (k - 1 - i))) & 3) as usize] as char)
+        .collect()
+}
+
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' => b'a',
+        other => other,
+    }
+}
+
+// Bases other than A/C/G/T (e.g. N) are kept as they are
+pub fn reverse_complement(kmer: &str) -> String {
+    kmer.bytes().rev().map(|base| complement(base) as char).collect()
+}
+
+// Lexically smaller of a k-mer and its reverse complement
+pub fn canonical_kmer(kmer: &str) -> String {
+    let reverse: String = reverse_complement(kmer);
+    if reverse.as_str() < kmer { reverse } else { kmer.to_string() }
+}
+
+// A k-mer equal to its own reverse complement, e.g. the EcoRI site GAATTC.
+// Only A/C/G/T qualify, so no k-mer of odd length is a palindrome: its middle
+// base would have to be its own complement.
+pub fn is_palindrome(kmer: &[u8]) -> bool {
+    kmer.iter().zip(kmer.iter().rev()).all(|(&base, &mirror)| complement(base) == mirror && complement(base) != base)
+}
+
+// Reverse complement on the packed form: complementing is XOR with 0b11 per base,
+// reversing swaps the 2-bit groups, after which the k bases sit in the top bits
+pub fn reverse_complement_u64(kmer: u64, k: usize) -> u64 {
+    let mut x: u64 = !kmer;
+    x = ((x >> 2) & 0x3333_3333_3333_3333) | ((x & 0x3333_3333_3333_3333) << 2);
+    x = ((x >> 4) & 0x0f0f_0f0f_0f0f_0f0f) | ((x & 0x0f0f_0f0f_0f0f_0f0f) << 4);
+    x = x.swap_bytes();
+    x >> (64 - 2 * k)
+}
+
+pub fn canonical_u64(kmer: u64, k: usize) -> u64 {
+    kmer.min(reverse_complement_u64(kmer, k))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample::splitmix64;
+
+    #[test]
+    fn canonical_u64_matches_canonical_kmer() {
+        let mut state: u64 = 0;
+        for _ in 0..10_000 {
+            state = splitmix64(state);
+            let k: usize = (state % 32) as usize + 1;
+            state = splitmix64(state);
+            let kmer: String = decode_kmer(state >> (64 - 2 * k), k);
+            let encoded: u64 = encode_kmer(&kmer).unwrap();
+            assert_eq!(decode_kmer(canonical_u64(encoded, k), k), canonical_kmer(&kmer), "k-mer {}", kmer);
+        }
+    }
+
+    #[test]
+    fn palindromes_equal_their_reverse_complement() {
+        assert!(is_palindrome(b"ACGT"));
+        assert!(is_palindrome(b"GAATTC"));
+        assert!(!is_palindrome(b"ACGA"));
+        assert!(!is_palindrome(b"ACNGT"));
+        assert!(is_palindrome(b"acgt"));
+    }
+}
@@ -0,0 +1,67 @@
+pub mod alphabet;
+pub mod bam;
+pub mod binary;
+pub mod bloom;
+pub mod checkpoint;
+pub mod checksum;
+pub mod collapse;
+pub mod count;
+pub mod counter;
+pub mod diff;
+pub mod error;
+pub mod estimate;
+pub mod inflate;
+pub mod interrupt;
+pub mod kmer;
+pub mod log;
+pub mod matrix;
+pub mod merge;
+pub mod minimizer;
+pub mod output;
+pub mod pairs;
+pub mod preprocess;
+pub mod profile;
+pub mod quality;
+pub mod regions;
+pub mod report;
+pub mod sample;
+pub mod selftest;
+pub mod sequences;
+pub mod unique;
+pub mod version;
+pub mod weighted;
+
+#[cfg(test)]
+mod testing;
+
+pub use alphabet::Alphabet;
+pub use bam::bam_records;
+pub use binary::{is_binary, load_binary, read_varint, save_binary, write_varint, BINARY_FILE};
+pub use checkpoint::{checkpoint_path, count_kmers_checkpointed, count_settings, load_checkpoint, save_checkpoint, Checkpoint, CheckpointOptions};
+pub use checksum::checksum;
+pub use collapse::{collapse_errors, DEFAULT_COLLAPSE_RATIO};
+pub use count::{add_sequence, auto_threads, build_pool, count_kmer_set, count_kmers, fold_canonical, CountOptions, Strand, DEFAULT_IGNORE_CHARS, WILDCARD};
+pub use counter::{Count, CountType};
+pub use diff::{diff_counts, save_diff, DiffFilter, DiffRow};
+pub use error::{KmerError, EXIT_ARGUMENT, EXIT_EMPTY, EXIT_INPUT, EXIT_INTERRUPTED, EXIT_IO, EXIT_OVERFLOW, EXIT_SELFTEST};
+pub use estimate::{estimate_distinct_kmers, ESTIMATE_SAMPLE_BASES, projected_map_bytes, projected_peak_bytes, HyperLogLog};
+pub use inflate::{gunzip, inflate, is_gzip};
+pub use interrupt::install_sigint_handler;
+pub use kmer::{canonical_kmer, canonical_u64, decode_kmer, encode_kmer, is_palindrome, reverse_complement, reverse_complement_u64};
+pub use log::{set_level, Level};
+pub use matrix::{count_matrix, save_matrix, CountMatrix};
+pub use merge::{merge_counts, merge_sorted_files, merge_weighted, save_kmers_external, save_merged};
+pub use minimizer::{canonical_minimizers, Tiebreak};
+pub use output::{auto_output_path, hp_path, k_path, load_counts, save_debruijn, save_frequencies, save_kmer_set, save_kmers, save_kmers_both_forms, save_kmers_combined_k, save_kmers_encoded, save_kmers_fasta, save_jellyfish, save_jsonl, save_kmers_split, shard_of, shard_path, OutputFormat, SortOrder, DEBRUIJN_FILE, DEFAULT_WRITE_BUFFER, FASTA_FILE, JELLYFISH_FILE, JSONL_FILE, OUTPUT_FILE, STDOUT};
+pub use pairs::{count_pairs, save_pairs, KmerPair, PAIRS_FILE};
+pub use preprocess::{check_adapter, circularize, collapse_homopolymers, filter_min_length, legacy_windows, longest_record, trim_adapter, trim_ns, trim_record_adapter, trim_record_ns, DEFAULT_ADAPTER_MISMATCHES};
+pub use profile::{gc_profile, save_gc_profile, GcBucket};
+pub use quality::{decode_quality, quality_histogram, rebase_qualities, save_quality_histogram, trim_by_quality, trim_record_quality, PhredOffset};
+pub use regions::{load_bed, select_regions, Regions};
+pub use report::RunReport;
+pub use sample::reservoir_sample;
+pub use selftest::{random_sequences, selftest, SelftestReport};
+pub use sequences::{expand_inputs, get_records, get_records_parallel, is_bam, records, sample_records, resolve_format, Format, ReadOptions, Record, DEFAULT_READ_BUFFER, STDIN};
+pub use unique::{canonical_table, save_unique, unique_kmers};
+pub use version::version;
+pub use weighted::{base_accuracy, count_weighted_kmers, save_weighted};
@@ -0,0 +1,71 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// Verbosity of the diagnostics kmeRS prints. Every level goes to standard
+// error, so standard output only ever carries results (e.g. --checksum).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+// Progress, banners and the run summary (hidden by -q)
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Info) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+// Malformed input that is tolerated, skipped files, overflows
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Warn) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+// Details only shown with -v
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The only test that changes the level, the others run at the default
+    #[test]
+    fn quiet_hides_info_and_verbose_shows_debug() {
+        assert!(enabled(Level::Info) && !enabled(Level::Debug));
+
+        set_level(Level::Warn);
+        assert!(!enabled(Level::Info) && !enabled(Level::Debug));
+        assert!(enabled(Level::Warn) && enabled(Level::Error));
+
+        set_level(Level::Debug);
+        assert!(enabled(Level::Debug) && enabled(Level::Info));
+
+        set_level(Level::Info);
+    }
+}
@@ -1,167 +1,1394 @@
 use std::env;
-use std::fs::File;
-use std::collections::HashMap;
-use std::io::{self, BufRead, BufReader, Write};
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Mutex;
-use std::time::Instant;
-use rayon::{self, ThreadPoolBuilder, prelude::*};
-
-fn get_sequences_from_fasta(file: &String) -> Vec<String> {
-
-    let file = File::open(file).expect("Unable to open file");
-    let reader = BufReader::new(file);
-
-    let mut sequences: Vec<String> = Vec::new();
-    let mut current_sequence: String = String::new();
-
-    for line in reader.lines() {
-        let line = line.expect("Unable to read line");
-        if line.starts_with('>') {
-            if !current_sequence.is_empty() {
-                sequences.push(current_sequence.clone());
-                current_sequence.clear();
-            }
-        } else {
-            current_sequence.push_str(&line);
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use rayon::ThreadPool;
+use kmers::{auto_output_path, auto_threads, build_pool, checksum, install_sigint_handler, count_kmer_set, count_kmers, expand_inputs, get_records_parallel, is_bam, rebase_qualities, sample_records, save_debruijn, save_frequencies, save_kmer_set, save_kmers, save_jsonl, save_kmers_split, trim_record_quality};
+use kmers::{debug, info, warn, set_level, Level};
+use kmers::{save_binary, save_jellyfish, save_kmers_both_forms, selftest, SelftestReport, BINARY_FILE, JELLYFISH_FILE};
+use kmers::{checkpoint_path, count_kmers_checkpointed, load_checkpoint, Checkpoint, CheckpointOptions};
+use kmers::{count_matrix, save_matrix, CountMatrix};
+use kmers::{is_palindrome, k_path, quality_histogram, save_kmers_combined_k, save_kmers_encoded, save_kmers_fasta, save_quality_histogram, FASTA_FILE};
+use kmers::{legacy_windows, canonical_table, save_unique, unique_kmers, circularize, count_pairs, estimate_distinct_kmers, ESTIMATE_SAMPLE_BASES, fold_canonical, projected_map_bytes, projected_peak_bytes, save_pairs, collapse_errors, collapse_homopolymers, hp_path, merge_counts, merge_weighted, save_kmers_external, merge_sorted_files, reservoir_sample, save_merged, version};
+use kmers::{diff_counts, filter_min_length, longest_record, gc_profile, save_gc_profile, load_bed, load_counts, save_diff, select_regions, count_weighted_kmers, save_weighted, check_adapter, trim_record_adapter, trim_record_ns, DEFAULT_ADAPTER_MISMATCHES};
+use kmers::{Alphabet, Count, CountOptions, KmerPair, PAIRS_FILE, STDOUT, CountType, DEBRUIJN_FILE, DEFAULT_COLLAPSE_RATIO, DEFAULT_IGNORE_CHARS, DEFAULT_READ_BUFFER, DEFAULT_WRITE_BUFFER, JSONL_FILE, OUTPUT_FILE, OutputFormat, ReadOptions, SortOrder, DiffFilter, Format, KmerError, EXIT_INTERRUPTED, PhredOffset, Record, RunReport, Strand, Tiebreak, STDIN};
+
+const USAGE: &str = "Usage: kmeRS <infile|dir>... <k> <threads|auto> [options]
+       kmeRS diff <counts_a> <counts_b> [--min-abs-change N] [--min-fold-change F] [--output PATH]
+       kmeRS merge <counts>... [--external] [--weights W1,W2,...] [--precision N] [--output PATH]
+       kmeRS unique <target> <others>... [--max-other N] [--canonical] [--output PATH]
+       kmeRS matrix <counts>... [--output PATH] [--index PATH]
+       kmeRS selftest [--sequences N] [--length L] [--k K] [--threads T] [--seed S]";
+
+struct Args {
+    files: Vec<String>,
+    k: usize,
+    // Every k to count, k is the first of them
+    ks: Vec<usize>,
+    // 0: scale with the input, see auto_threads
+    threads: usize,
+    report: Option<String>,
+    recursive: bool,
+    bloom: bool,
+    bloom_size: usize,
+    phred: PhredOffset,
+    trim_quality: Option<u8>,
+    set: bool,
+    split: Option<usize>,
+    checksum: bool,
+    canonical: bool,
+    regions: Option<String>,
+    min_seq_len: Option<usize>,
+    trim_n: bool,
+    normalize: bool,
+    precision: usize,
+    gc_profile: Option<String>,
+    strict: bool,
+    no_write: bool,
+    skip_masked: bool,
+    sort: SortOrder,
+    reservoir: Option<usize>,
+    seed: u64,
+    canonical_minimizers: Option<usize>,
+    minimizer_tiebreak: Tiebreak,
+    debruijn: bool,
+    ignore_chars: Vec<u8>,
+    count_type: CountType,
+    auto_output: bool,
+    combined: bool,
+    collapse_errors: bool,
+    format_out: OutputFormat,
+    strand: Strand,
+    read_buffer: usize,
+    write_buffer: usize,
+    format: Option<Format>,
+    hp_both: bool,
+    circular: bool,
+    alphabet_file: Option<String>,
+    external: bool,
+    pairs: bool,
+    report_canonical: bool,
+    estimate_memory: bool,
+    skip_secondary: bool,
+    adapter: Option<String>,
+    adapter_mismatches: usize,
+    qual_weighted: bool,
+    checkpoint_every: Option<usize>,
+    resume: Option<String>,
+    central_wildcard: bool,
+    longest_only: bool,
+    progress_every: Option<usize>,
+    emit_both_forms: bool,
+    combined_k_output: bool,
+    palindromes_only: bool,
+    // Seconds of wall-clock time before counting stops
+    time_limit: Option<f64>,
+    qual_hist: Option<String>,
+    encode_output: bool,
+    emit_fasta: bool,
+    // Count table path instead of the format's default name, STDOUT for -
+    output: Option<String>,
+    // Deprecated, the original counter's off-by-one
+    legacy: bool,
+}
+
+fn argument_error<T>(message: &str) -> Result<T, KmerError> {
+    Err(KmerError::Argument(message.to_string()))
+}
+
+fn next_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, KmerError> {
+    args.next().ok_or_else(|| KmerError::Argument(format!("{} requires a value", flag)))
+}
+
+fn parse_value<T: FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T, KmerError> {
+    next_value(args, flag)?.parse::<T>().map_err(|_| KmerError::Argument(format!("Invalid value for {}", flag)))
+}
+
+// Buffer sizes are given in KB on the command line
+fn parse_kilobytes(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<usize, KmerError> {
+    let kilobytes: usize = parse_value(args, flag)?;
+    if kilobytes == 0 {
+        return Err(KmerError::Argument(format!("{} must be at least 1 KB", flag)));
+    }
+    Ok(kilobytes * 1024)
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, KmerError> {
+
+    let mut positional: Vec<String> = Vec::new();
+    let mut parsed = Args {
+        files: Vec::new(),
+        k: 0,
+        ks: Vec::new(),
+        threads: 0,
+        report: None,
+        recursive: false,
+        bloom: false,
+        bloom_size: 64,
+        phred: PhredOffset::Phred33,
+        trim_quality: None,
+        set: false,
+        split: None,
+        checksum: false,
+        canonical: false,
+        regions: None,
+        min_seq_len: None,
+        trim_n: false,
+        normalize: false,
+        precision: 6,
+        gc_profile: None,
+        strict: false,
+        no_write: false,
+        skip_masked: false,
+        sort: SortOrder::Unsorted,
+        reservoir: None,
+        seed: 0,
+        canonical_minimizers: None,
+        minimizer_tiebreak: Tiebreak::Leftmost,
+        debruijn: false,
+        ignore_chars: DEFAULT_IGNORE_CHARS.to_vec(),
+        count_type: CountType::U32,
+        auto_output: false,
+        combined: false,
+        collapse_errors: false,
+        format_out: OutputFormat::Tsv,
+        strand: Strand::Forward,
+        read_buffer: DEFAULT_READ_BUFFER,
+        write_buffer: DEFAULT_WRITE_BUFFER,
+        format: None,
+        hp_both: false,
+        circular: false,
+        alphabet_file: None,
+        external: false,
+        pairs: false,
+        report_canonical: false,
+        estimate_memory: false,
+        skip_secondary: false,
+        adapter: None,
+        adapter_mismatches: DEFAULT_ADAPTER_MISMATCHES,
+        qual_weighted: false,
+        checkpoint_every: None,
+        resume: None,
+        central_wildcard: false,
+        longest_only: false,
+        progress_every: None,
+        emit_both_forms: false,
+        combined_k_output: false,
+        palindromes_only: false,
+        time_limit: None,
+        qual_hist: None,
+        encode_output: false,
+        emit_fasta: false,
+        output: None,
+        legacy: false,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--report" => parsed.report = Some(next_value(&mut args, "--report")?),
+            "--recursive" => parsed.recursive = true,
+            "--bloom" => parsed.bloom = true,
+            "--bloom-size" => parsed.bloom_size = parse_value(&mut args, "--bloom-size")?,
+            "--phred" => parsed.phred = PhredOffset::parse(&next_value(&mut args, "--phred")?)?,
+            "--trim-qual" => parsed.trim_quality = Some(parse_value(&mut args, "--trim-qual")?),
+            "--set" => parsed.set = true,
+            "--split" => parsed.split = Some(parse_value(&mut args, "--split")?),
+            "--checksum" => parsed.checksum = true,
+            "--canonical" => parsed.canonical = true,
+            "--regions" => parsed.regions = Some(next_value(&mut args, "--regions")?),
+            "--min-seq-len" => parsed.min_seq_len = Some(parse_value(&mut args, "--min-seq-len")?),
+            "--trim-n" => parsed.trim_n = true,
+            "--normalize" => parsed.normalize = true,
+            "--precision" => parsed.precision = parse_value(&mut args, "--precision")?,
+            "--gc-profile" => parsed.gc_profile = Some(next_value(&mut args, "--gc-profile")?),
+            "--strict" => parsed.strict = true,
+            "--no-write" => parsed.no_write = true,
+            "--skip-masked" => parsed.skip_masked = true,
+            "--sort" => parsed.sort = SortOrder::parse(&next_value(&mut args, "--sort")?)?,
+            "--reservoir" => parsed.reservoir = Some(parse_value(&mut args, "--reservoir")?),
+            "--seed" => parsed.seed = parse_value(&mut args, "--seed")?,
+            "--debruijn" => parsed.debruijn = true,
+            "--ignore-chars" => parsed.ignore_chars = next_value(&mut args, "--ignore-chars")?.into_bytes(),
+            "--auto-output" => parsed.auto_output = true,
+            "--combined" => parsed.combined = true,
+            "--collapse-errors" => parsed.collapse_errors = true,
+            "--read-buffer" => parsed.read_buffer = parse_kilobytes(&mut args, "--read-buffer")?,
+            "--write-buffer" => parsed.write_buffer = parse_kilobytes(&mut args, "--write-buffer")?,
+            // Sequences are always counted one by one, so no k-mer spans two of them
+            "--no-boundary-kmers" => {}
+            "--circular" => parsed.circular = true,
+            "--pairs" => parsed.pairs = true,
+            "--report-canonical" => parsed.report_canonical = true,
+            "--estimate-memory" => parsed.estimate_memory = true,
+            "--skip-secondary" => parsed.skip_secondary = true,
+            "--adapter" => parsed.adapter = Some(next_value(&mut args, "--adapter")?),
+            "--qual-weighted" => parsed.qual_weighted = true,
+            "--central-wildcard" => parsed.central_wildcard = true,
+            "--longest-only" => parsed.longest_only = true,
+            "--emit-both-forms" => parsed.emit_both_forms = true,
+            "--combined-k-output" => parsed.combined_k_output = true,
+            "--palindromes-only" => parsed.palindromes_only = true,
+            "--encode-output" => parsed.encode_output = true,
+            "--emit-fasta" => parsed.emit_fasta = true,
+            "--legacy" => parsed.legacy = true,
+            "--output" => parsed.output = Some(next_value(&mut args, "--output")?),
+            "--qual-hist" => parsed.qual_hist = Some(next_value(&mut args, "--qual-hist")?),
+            "--time-limit" => parsed.time_limit = Some(parse_value(&mut args, "--time-limit")?),
+            "--progress-every" => parsed.progress_every = Some(parse_value(&mut args, "--progress-every")?),
+            "--checkpoint-every" => parsed.checkpoint_every = Some(parse_value(&mut args, "--checkpoint-every")?),
+            "--resume" => parsed.resume = Some(next_value(&mut args, "--resume")?),
+            "--adapter-mismatches" => parsed.adapter_mismatches = parse_value(&mut args, "--adapter-mismatches")?,
+            "--external" => parsed.external = true,
+            "--alphabet-file" => parsed.alphabet_file = Some(next_value(&mut args, "--alphabet-file")?),
+            "--hp-both" => parsed.hp_both = true,
+            "--format" => parsed.format = Some(Format::parse(&next_value(&mut args, "--format")?)?),
+            "--strand" => parsed.strand = Strand::parse(&next_value(&mut args, "--strand")?)?,
+            "--format-out" => parsed.format_out = OutputFormat::parse(&next_value(&mut args, "--format-out")?)?,
+            "--count-type" => parsed.count_type = CountType::parse(&next_value(&mut args, "--count-type")?)?,
+            "--canonical-minimizers" => parsed.canonical_minimizers = Some(parse_value(&mut args, "--canonical-minimizers")?),
+            "--minimizer-tiebreak" => parsed.minimizer_tiebreak = Tiebreak::parse(&next_value(&mut args, "--minimizer-tiebreak")?)?,
+            _ => positional.push(arg),
         }
     }
-    if !current_sequence.is_empty() {
-        sequences.push(current_sequence);
+
+    if positional.len() < 3 {
+        return argument_error(USAGE);
+    }
+
+    // The last two positionals are k and threads, everything before is input
+    let threads: String = positional.pop().unwrap();
+    parsed.threads = if threads == "auto" { 0 } else { threads.parse::<usize>().map_err(|_| KmerError::Argument(String::from("Invalid value for threads")))? };
+    // A comma-separated list counts several k on the same sequences
+    parsed.ks = positional.pop().unwrap().split(',')
+        .map(|k| k.parse::<usize>().ok().filter(|&k| k >= 1).ok_or_else(|| KmerError::Argument(String::from("Invalid value for k, it must be at least 1"))))
+        .collect::<Result<Vec<usize>, KmerError>>()?;
+    parsed.k = parsed.ks[0];
+    parsed.files = positional;
+
+    if parsed.canonical_minimizers.is_some() && (parsed.k == 0 || parsed.k > 32) {
+        return argument_error("--canonical-minimizers needs 1 <= k <= 32");
+    }
+    if parsed.canonical_minimizers == Some(0) {
+        return argument_error("--canonical-minimizers needs a window of at least 1 k-mer");
+    }
+    if parsed.adapter.as_deref() == Some("") {
+        return argument_error("--adapter needs a non-empty sequence");
+    }
+    if parsed.split == Some(0) {
+        return argument_error("--split needs at least one shard");
+    }
+    if parsed.split.is_some() && parsed.set {
+        return argument_error("--split cannot be combined with --set");
+    }
+    if parsed.normalize && (parsed.set || parsed.split.is_some()) {
+        return argument_error("--normalize cannot be combined with --set or --split");
+    }
+    if parsed.debruijn && (parsed.set || parsed.split.is_some() || parsed.normalize) {
+        return argument_error("--debruijn cannot be combined with --set, --split or --normalize");
+    }
+    if parsed.format_out != OutputFormat::Tsv && (parsed.set || parsed.split.is_some() || parsed.normalize || parsed.debruijn) {
+        return argument_error("--format-out jsonl, binary and jellyfish cannot be combined with --set, --split, --normalize or --debruijn");
+    }
+    if parsed.auto_output && parsed.files.iter().any(|file| file == STDIN) {
+        return argument_error("--auto-output cannot name the output of standard input");
+    }
+    if parsed.hp_both && (parsed.set || parsed.gc_profile.is_some()) {
+        return argument_error("--hp-both cannot be combined with --set or --gc-profile");
+    }
+    if parsed.central_wildcard && parsed.k.is_multiple_of(2) {
+        return argument_error("--central-wildcard needs an odd k");
+    }
+    if parsed.central_wildcard && (parsed.canonical_minimizers.is_some() || parsed.alphabet_file.is_some() || parsed.collapse_errors || parsed.format_out == OutputFormat::Binary) {
+        return argument_error("--central-wildcard cannot be combined with --canonical-minimizers, --alphabet-file, --collapse-errors or --format-out binary");
+    }
+    if parsed.emit_both_forms && !(parsed.canonical || parsed.report_canonical) {
+        return argument_error("--emit-both-forms needs --canonical or --report-canonical");
+    }
+    if parsed.emit_both_forms && (parsed.set || parsed.pairs || parsed.qual_weighted || parsed.split.is_some() || parsed.normalize || parsed.debruijn
+        || parsed.external || parsed.alphabet_file.is_some() || parsed.format_out != OutputFormat::Tsv) {
+        return argument_error("--emit-both-forms only writes the plain TSV counts of A/C/G/T k-mers");
+    }
+    if (parsed.ks.len() > 1 || parsed.combined_k_output) && (parsed.set || parsed.pairs || parsed.qual_weighted || parsed.hp_both || parsed.split.is_some()
+        || parsed.normalize || parsed.debruijn || parsed.external || parsed.format_out != OutputFormat::Tsv || parsed.collapse_errors
+        || parsed.checksum || parsed.gc_profile.is_some() || parsed.canonical_minimizers.is_some() || parsed.central_wildcard || parsed.circular
+        || parsed.reservoir.is_some() || parsed.estimate_memory || parsed.emit_both_forms || parsed.encode_output || parsed.emit_fasta || parsed.auto_output
+        || parsed.checkpoint_every.is_some() || parsed.resume.is_some()) {
+        return argument_error("Several k values and --combined-k-output only work with plain TSV counting");
+    }
+    if parsed.palindromes_only && (parsed.set || parsed.pairs || parsed.qual_weighted || parsed.central_wildcard) {
+        return argument_error("--palindromes-only cannot be combined with --set, --pairs, --qual-weighted or --central-wildcard");
+    }
+    if parsed.palindromes_only && parsed.ks.iter().any(|k| !k.is_multiple_of(2)) {
+        warn!("Warning: no k-mer of odd length is its own reverse complement, --palindromes-only keeps nothing for odd k");
+    }
+    if parsed.encode_output && (parsed.k == 0 || parsed.k > 32) {
+        return argument_error("--encode-output needs 1 <= k <= 32");
+    }
+    if parsed.encode_output && (parsed.set || parsed.pairs || parsed.qual_weighted || parsed.split.is_some() || parsed.normalize || parsed.debruijn
+        || parsed.external || parsed.emit_both_forms || parsed.central_wildcard || parsed.alphabet_file.is_some() || parsed.format_out != OutputFormat::Tsv) {
+        return argument_error("--encode-output only writes the plain TSV counts of A/C/G/T k-mers");
+    }
+    if parsed.emit_fasta && (parsed.set || parsed.pairs || parsed.qual_weighted || parsed.split.is_some() || parsed.normalize || parsed.debruijn
+        || parsed.external || parsed.emit_both_forms || parsed.encode_output || parsed.format_out != OutputFormat::Tsv) {
+        return argument_error("--emit-fasta cannot be combined with other counting or output modes");
+    }
+    if parsed.legacy && (parsed.pairs || parsed.qual_weighted || parsed.circular || parsed.canonical_minimizers.is_some()) {
+        return argument_error("--legacy cannot be combined with --pairs, --qual-weighted, --circular or --canonical-minimizers");
+    }
+    if parsed.legacy {
+        warn!("Warning: --legacy is deprecated and will be removed in the next release, it only reproduces the off-by-one of the old FASTA counts (the missing last k-mer of every sequence), not the empty output of its FASTQ reader");
+    }
+    if parsed.output.is_some() && parsed.auto_output {
+        return argument_error("--output cannot be combined with --auto-output");
+    }
+    if parsed.output.as_deref() == Some(STDOUT) && (parsed.checksum || parsed.hp_both || parsed.split.is_some()
+        || parsed.checkpoint_every.is_some() || parsed.resume.is_some() || (parsed.ks.len() > 1 && !parsed.combined_k_output)) {
+        return argument_error("--output - writes a single table to standard output, it cannot be combined with --checksum, --hp-both, --split, checkpoints or one file per k");
+    }
+    if parsed.time_limit.is_some_and(|seconds| !seconds.is_finite() || seconds <= 0.0) {
+        return argument_error("--time-limit needs a positive number of seconds");
+    }
+    if parsed.checkpoint_every == Some(0) {
+        return argument_error("--checkpoint-every needs at least one sequence");
+    }
+    if (parsed.checkpoint_every.is_some() || parsed.resume.is_some()) && (parsed.set || parsed.pairs || parsed.qual_weighted || parsed.hp_both || parsed.bloom) {
+        return argument_error("--checkpoint-every and --resume only apply to plain counting, not --set, --pairs, --qual-weighted, --hp-both or --bloom");
+    }
+    if parsed.qual_weighted && (parsed.pairs || parsed.set || parsed.split.is_some() || parsed.normalize || parsed.debruijn || parsed.external || parsed.hp_both
+        || parsed.canonical_minimizers.is_some() || parsed.bloom || parsed.collapse_errors || parsed.checksum || parsed.gc_profile.is_some()
+        || parsed.report_canonical || parsed.circular || parsed.reservoir.is_some() || parsed.format_out != OutputFormat::Tsv) {
+        return argument_error("--qual-weighted only writes the plain weighted table and cannot be combined with other counting or output modes");
+    }
+    if parsed.pairs && (parsed.set || parsed.split.is_some() || parsed.normalize || parsed.debruijn || parsed.external || parsed.hp_both
+        || parsed.canonical_minimizers.is_some() || parsed.bloom || parsed.collapse_errors || parsed.checksum || parsed.gc_profile.is_some()
+        || parsed.report_canonical || parsed.format_out != OutputFormat::Tsv) {
+        return argument_error("--pairs only writes the plain pair table and cannot be combined with other output modes");
+    }
+    if parsed.external && parsed.sort != SortOrder::Lexical {
+        return argument_error("--external needs --sort lexical");
+    }
+    if parsed.external && (parsed.set || parsed.split.is_some() || parsed.normalize || parsed.debruijn || parsed.format_out != OutputFormat::Tsv) {
+        return argument_error("--external only writes the plain TSV counts");
+    }
+    if parsed.alphabet_file.is_some() && (parsed.canonical_minimizers.is_some() || parsed.collapse_errors) {
+        return argument_error("--alphabet-file cannot be combined with --canonical-minimizers or --collapse-errors, which assume A/C/G/T");
+    }
+    if parsed.combined && !parsed.auto_output {
+        return argument_error("--combined only applies to --auto-output");
+    }
+    // One run per input would overwrite these single-path outputs
+    if parsed.auto_output && !parsed.combined && (parsed.report.is_some() || parsed.gc_profile.is_some() || parsed.reservoir.is_some()) {
+        return argument_error("--report, --gc-profile and --reservoir need --combined when used with --auto-output");
+    }
+    if (parsed.checksum || parsed.gc_profile.is_some() || parsed.collapse_errors || parsed.report_canonical) && parsed.set {
+        return argument_error("--checksum, --gc-profile, --collapse-errors and --report-canonical cannot be combined with --set");
+    }
+
+    Ok(parsed)
+}
+
+// Fixed output file name of the selected representation
+fn default_output(args: &Args) -> &'static str {
+    if args.debruijn {
+        DEBRUIJN_FILE
+    } else if args.format_out == OutputFormat::Jsonl {
+        JSONL_FILE
+    } else if args.format_out == OutputFormat::Binary {
+        BINARY_FILE
+    } else if args.format_out == OutputFormat::Jellyfish {
+        JELLYFISH_FILE
+    } else if args.emit_fasta {
+        FASTA_FILE
+    } else {
+        OUTPUT_FILE
+    }
+}
+
+// Writes the count table in the representation selected on the command line.
+// Without an explicit output path the fixed default file names are used.
+fn write_counts<C: Count>(kmer_hashmap: HashMap<String, C>, output: Option<&str>, args: &Args) -> Result<(), KmerError> {
+    let path: &str = output.unwrap_or(default_output(args));
+    if args.debruijn {
+        save_debruijn(kmer_hashmap, path, args.write_buffer, args.sort)?;
+    } else if args.format_out == OutputFormat::Jsonl {
+        save_jsonl(kmer_hashmap, path, args.write_buffer, args.sort)?;
+    } else if args.format_out == OutputFormat::Binary {
+        save_binary(kmer_hashmap, path, args.write_buffer, args.sort)?;
+    } else if args.format_out == OutputFormat::Jellyfish {
+        save_jellyfish(kmer_hashmap, path, args.write_buffer)?;
+    } else if args.normalize {
+        save_frequencies(kmer_hashmap, path, args.write_buffer, args.precision, args.sort)?;
+    } else if args.external {
+        save_kmers_external(kmer_hashmap, path)?;
+    } else if args.emit_both_forms {
+        save_kmers_both_forms(kmer_hashmap, path, args.write_buffer, args.sort)?;
+    } else if args.emit_fasta {
+        save_kmers_fasta(kmer_hashmap, path, args.write_buffer, args.sort)?;
+    } else if args.encode_output {
+        let skipped: usize = save_kmers_encoded(kmer_hashmap, path, args.write_buffer, args.k, args.sort)?;
+        if skipped > 0 {
+            warn!("Warning: left out {} k-mers with bases other than A/C/G/T, they have no 2-bit encoding", skipped);
+        }
+    } else if let Some(shards) = args.split {
+        save_kmers_split(kmer_hashmap, path, args.write_buffer, shards, args.sort)?;
+    } else {
+        save_kmers(kmer_hashmap, path, args.write_buffer, args.sort)?;
+    }
+    Ok(())
+}
+
+// count_and_write with the counter width selected on the command line
+fn count_with_type(sequences: &[String], output: Option<&str>, label: Option<&str>, pool: &ThreadPool, options: &CountOptions, args: &Args, timings: &mut Vec<(String, Duration)>) -> Result<(usize, Option<u64>), KmerError> {
+    match args.count_type {
+        CountType::U16 => count_and_write::<u16>(sequences, output, label, pool, options, args, timings),
+        CountType::U32 => count_and_write::<u32>(sequences, output, label, pool, options, args, timings),
+        CountType::U64 => count_and_write::<u64>(sequences, output, label, pool, options, args, timings),
+    }
+}
+
+// Counts the adjacent k-mer pairs with counters of type C and writes the pair
+// table, returns the number of distinct and total pairs
+fn count_and_write_pairs<C: Count>(sequences: &[String], output: Option<&str>, pool: &ThreadPool, options: &CountOptions, args: &Args, timings: &mut Vec<(String, Duration)>) -> Result<(usize, Option<u64>), KmerError> {
+
+    let phase = Instant::now();
+    let pair_hashmap: HashMap<KmerPair, C> = count_pairs(sequences, args.k, pool, options)?;
+    timings.push((String::from("count"), phase.elapsed()));
+
+    let distinct_pairs: usize = pair_hashmap.len();
+    let total_pairs: u64 = pair_hashmap.values().map(|count| count.to_u64()).sum();
+
+    if !args.no_write {
+        info!("-------------------------------------");
+        info!("Writing kmer pairs to file");
+        info!("-------------------------------------");
+
+        let phase = Instant::now();
+        save_pairs(pair_hashmap, output.unwrap_or(PAIRS_FILE), args.write_buffer, args.sort)?;
+        timings.push((String::from("write"), phase.elapsed()));
     }
-    return sequences;
+    Ok((distinct_pairs, Some(total_pairs)))
 }
 
-fn get_sequences_from_fastq(file: &String) -> Vec<String> {
+// The options that shape the sequences before they are counted, stored in
+// checkpoints so a resumed run has to read its input the same way
+fn input_settings(args: &Args) -> String {
+    format!(
+        "format={:?} skip-secondary={} longest-only={} phred={:?} trim-n={} adapter={:?} adapter-mismatches={} trim-qual={:?} regions={:?} min-seq-len={:?} legacy={} circular={}",
+        args.format, args.skip_secondary, args.longest_only, args.phred, args.trim_n, args.adapter, args.adapter_mismatches,
+        args.trim_quality, args.regions, args.min_seq_len, args.legacy, args.circular
+    )
+}
 
-    let file = File::open(file).expect("Unable to open file");
-    let reader = BufReader::new(file);
-    let sequences: Vec<String> = Vec::new();
+// Counts the k-mers into a map with counters of type C and writes every requested
+// output, returns the number of distinct and total k-mers. The label tells the
+// tables of one run apart in the printed checksum.
+fn count_and_write<C: Count>(sequences: &[String], output: Option<&str>, label: Option<&str>, pool: &ThreadPool, options: &CountOptions, args: &Args, timings: &mut Vec<(String, Duration)>) -> Result<(usize, Option<u64>), KmerError> {
 
-    let mut current_sequence: String = String::new();
+    if args.ks.len() > 1 || args.combined_k_output {
+        return count_and_write_ks::<C>(sequences, output, pool, options, args, timings);
+    }
 
-    let mut read: bool = false;
+    // A resumed run keeps checkpointing to the file it was resumed from
+    let checkpoint: Option<String> = match (&args.resume, args.checkpoint_every) {
+        (Some(path), _) => Some(path.clone()),
+        (None, Some(_)) => Some(checkpoint_path(output.unwrap_or(default_output(args)))),
+        (None, None) => None,
+    };
 
-    for line in reader.lines() {
-        let line = line.expect("Unable to read line");
-        if line.starts_with("@") {
-            read = true;
+    let phase = Instant::now();
+    let mut kmer_hashmap: HashMap<String, C> = match &checkpoint {
+        Some(path) => {
+            let resume: Option<Checkpoint<C>> = args.resume.as_deref().map(load_checkpoint).transpose()?;
+            let checkpointing = CheckpointOptions {
+                every: args.checkpoint_every.unwrap_or(usize::MAX),
+                path: path.clone(),
+                input_settings: input_settings(args),
+            };
+            count_kmers_checkpointed(sequences, args.k, pool, options, &checkpointing, resume)?
         }
-        if line.starts_with("+") {
-            read = false;
-            if !current_sequence.is_empty() {
-                current_sequence.clear();
-            }
+        None => count_kmers(sequences, args.k, pool, options)?,
+    };
+    timings.push((String::from("count"), phase.elapsed()));
+
+    // Strand-specific counting, canonical reporting. Jellyfish dumps are canonical.
+    if args.report_canonical || args.format_out == OutputFormat::Jellyfish {
+        kmer_hashmap = fold_canonical(kmer_hashmap, args.k, options)?;
+    }
+
+    // Fold single-base error k-mers into their abundant neighbors. The keys are
+    // canonical after --canonical or after the fold above.
+    if args.collapse_errors {
+        let canonical_keys: bool = args.canonical || args.report_canonical || args.format_out == OutputFormat::Jellyfish;
+        let phase = Instant::now();
+        let collapsed: usize = collapse_errors(&mut kmer_hashmap, DEFAULT_COLLAPSE_RATIO, canonical_keys, pool);
+        timings.push((String::from("collapse"), phase.elapsed()));
+        info!("Collapsed {} k-mers into a Hamming-1 neighbor", collapsed);
+    }
+
+    if args.palindromes_only {
+        kmer_hashmap.retain(|kmer, _| is_palindrome(kmer.as_bytes()));
+    }
+
+    let distinct_kmers: usize = kmer_hashmap.len();
+    let total_kmers: u64 = kmer_hashmap.values().map(|count| count.to_u64()).sum();
+
+    if args.checksum {
+        match label {
+            Some(label) => println!("Checksum ({}): {:016x}", label, checksum(&kmer_hashmap)),
+            None => println!("Checksum: {:016x}", checksum(&kmer_hashmap)),
         }
-        if read {
-            current_sequence.push_str(&line); 
+    }
+    if let Some(path) = &args.gc_profile {
+        save_gc_profile(&gc_profile(&kmer_hashmap, args.k), path)?;
+    }
+
+    if !args.no_write {
+        info!("-------------------------------------");
+        info!("Writing kmer counts to file");
+        info!("-------------------------------------");
+
+        let phase = Instant::now();
+        write_counts(kmer_hashmap, output, args)?;
+        timings.push((String::from("write"), phase.elapsed()));
+    }
+
+    // The checkpoint is only needed until the complete counts are written
+    if let Some(path) = &checkpoint {
+        if !options.stopped() && Path::new(path).exists() {
+            fs::remove_file(path)?;
         }
     }
-    return sequences;
+
+    Ok((distinct_kmers, Some(total_kmers)))
 }
 
-fn get_sequences(file: &String) -> Vec<String> {
-    if file.ends_with("a") {
-        let sequences: Vec< String> = get_sequences_from_fasta(file);
-        return sequences;
+// Counts every k of a comma-separated list on the same sequences. The tables go
+// to one file per k, or to a single file with a k column (--combined-k-output).
+// Returns the distinct and total k-mers summed over all k.
+fn count_and_write_ks<C: Count>(sequences: &[String], output: Option<&str>, pool: &ThreadPool, options: &CountOptions, args: &Args, timings: &mut Vec<(String, Duration)>) -> Result<(usize, Option<u64>), KmerError> {
+
+    let path: &str = output.unwrap_or(OUTPUT_FILE);
+    let mut tables: Vec<(usize, HashMap<String, C>)> = Vec::new();
+    let mut distinct_kmers: usize = 0;
+    let mut total_kmers: u64 = 0;
+
+    for &k in &args.ks {
+        let phase = Instant::now();
+        let mut kmer_hashmap: HashMap<String, C> = count_kmers(sequences, k, pool, options)?;
+        timings.push((format!("count_k{}", k), phase.elapsed()));
+
+        if args.report_canonical {
+            kmer_hashmap = fold_canonical(kmer_hashmap, k, options)?;
+        }
+        if args.palindromes_only {
+            kmer_hashmap.retain(|kmer, _| is_palindrome(kmer.as_bytes()));
+        }
+        distinct_kmers += kmer_hashmap.len();
+        total_kmers += kmer_hashmap.values().map(|count| count.to_u64()).sum::<u64>();
+
+        if args.no_write {
+            continue;
+        }
+        if args.combined_k_output {
+            tables.push((k, kmer_hashmap));
+        } else {
+            let phase = Instant::now();
+            save_kmers(kmer_hashmap, &k_path(path, k), args.write_buffer, args.sort)?;
+            timings.push((format!("write_k{}", k), phase.elapsed()));
+        }
     }
-    if file.ends_with("q") {
-        let sequences: Vec< String> = get_sequences_from_fastq(file);
-        return sequences;
+
+    if args.combined_k_output && !args.no_write {
+        info!("-------------------------------------");
+        info!("Writing kmer counts to file");
+        info!("-------------------------------------");
+
+        let phase = Instant::now();
+        save_kmers_combined_k(tables, path, args.write_buffer, args.sort)?;
+        timings.push((String::from("write"), phase.elapsed()));
     }
-    panic!("File must end with a or q to be recognized as FASTA or FASTQ");     
+
+    Ok((distinct_kmers, Some(total_kmers)))
 }
 
-fn count_kmers(file: &String, k: usize) -> HashMap<String, u32> {
+// kmeRS diff: per k-mer comparison of two count files
+fn run_diff(mut args: impl Iterator<Item = String>) -> Result<(), KmerError> {
 
-    // Shared HashMap protected by a Mutex for thread-safe updates
-    let kmer_hashmap = Mutex::new(HashMap::new());
+    let mut positional: Vec<String> = Vec::new();
+    let mut filter = DiffFilter::default();
+    let mut output: String = String::from("kmer_diff.tsv");
 
-    // Get sequences from the file
-    let sequences: Vec<String> = get_sequences(file);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--min-abs-change" => filter.min_abs_change = Some(parse_value(&mut args, "--min-abs-change")?),
+            "--min-fold-change" => filter.min_fold_change = Some(parse_value(&mut args, "--min-fold-change")?),
+            "--output" => output = next_value(&mut args, "--output")?,
+            _ => positional.push(arg),
+        }
+    }
 
-    println!("Read {} sequences", sequences.len());
-    println!("-------------------------------------");
-    println!("Processed sequences:");
+    if positional.len() != 2 {
+        return argument_error(USAGE);
+    }
+
+    let a: HashMap<String, u64> = load_counts(&positional[0])?;
+    let b: HashMap<String, u64> = load_counts(&positional[1])?;
 
-    // Atomic counter to track total processed sequences
-    let progress = AtomicUsize::new(0);
+    let rows = diff_counts(&a, &b, &filter);
+    save_diff(&rows, &output)?;
 
-    // Process sequences in parallel
-    sequences.par_iter().enumerate().for_each(|(_i, sequence)| {
+    info!("Wrote {} rows to {}", rows.len(), output);
 
-        // Local HashMap for each thread to reduce contention
-        let mut local_map: HashMap<String, u32> = HashMap::new();
+    Ok(())
+}
 
-        // Increment the processed sequences counter
-        let total_progress = progress.fetch_add(1, Ordering::Relaxed) + 1;
+// kmeRS merge: sums several count files, in memory or as a streaming merge of sorted inputs
+fn run_merge(mut args: impl Iterator<Item = String>) -> Result<(), KmerError> {
 
-        if total_progress % (sequences.len()/10) == 0 {
-            println!("{}", total_progress);
+    let mut inputs: Vec<String> = Vec::new();
+    let mut external: bool = false;
+    let mut weights: Option<Vec<f64>> = None;
+    let mut precision: usize = 6;
+    let mut output: String = String::from("kmer_merged.tsv");
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--external" => external = true,
+            "--weights" => {
+                let value: String = next_value(&mut args, "--weights")?;
+                weights = Some(value.split(',')
+                    .map(|weight| weight.parse::<f64>().ok().filter(|weight| weight.is_finite() && *weight >= 0.0))
+                    .collect::<Option<Vec<f64>>>()
+                    .ok_or_else(|| KmerError::Argument(String::from("--weights needs a comma-separated list of non-negative numbers")))?);
+            }
+            "--precision" => precision = parse_value(&mut args, "--precision")?,
+            "--output" => output = next_value(&mut args, "--output")?,
+            _ => inputs.push(arg),
+        }
+    }
+
+    if inputs.is_empty() {
+        return argument_error(USAGE);
+    }
+
+    if let Some(weights) = &weights {
+        if weights.len() != inputs.len() {
+            return argument_error(&format!("--weights has {} factors for {} inputs", weights.len(), inputs.len()));
+        }
+        if external {
+            return argument_error("--weights cannot be combined with --external");
+        }
+    }
+
+    let written: usize = if let Some(weights) = &weights {
+        let merged: HashMap<String, f64> = merge_weighted(&inputs, weights)?;
+        let written: usize = merged.len();
+        save_weighted(merged, &output, DEFAULT_WRITE_BUFFER, precision, SortOrder::Lexical)?;
+        written
+    } else if external {
+        merge_sorted_files(&inputs, &output)?
+    } else {
+        let merged = merge_counts(&inputs)?;
+        save_merged(&merged, &output)?;
+        merged.len()
+    };
+
+    info!("Wrote {} k-mers to {}", written, output);
+
+    Ok(())
+}
+
+// --auto-output path of an input, with the extension of the output format
+fn auto_output(input: &str, args: &Args) -> String {
+    let path: String = auto_output_path(input, args.k);
+    match args.format_out {
+        OutputFormat::Tsv if args.emit_fasta => Path::new(&path).with_extension("fa").to_string_lossy().into_owned(),
+        OutputFormat::Tsv => path,
+        OutputFormat::Jsonl => Path::new(&path).with_extension("jsonl").to_string_lossy().into_owned(),
+        OutputFormat::Binary => Path::new(&path).with_extension("bin").to_string_lossy().into_owned(),
+        OutputFormat::Jellyfish => Path::new(&path).with_extension("jf.txt").to_string_lossy().into_owned(),
+    }
+}
+
+// Reads, counts and writes one set of inputs, returns whether Ctrl-C cut the run short
+// pool is None for automatic thread scaling, which needs the input size
+fn run(files: &[String], output: Option<&str>, args: &Args, options: &CountOptions, pool: Option<&ThreadPool>) -> Result<bool, KmerError> {
+
+    let k: usize = args.k;
+
+    let mut filters: Vec<(String, f64)> = Vec::new();
+    if let Some(megabytes) = options.bloom_megabytes {
+        filters.push((String::from("bloom_min_count"), 2.0));
+        filters.push((String::from("bloom_size_mb"), megabytes as f64));
+    }
+
+    let mut timings: Vec<(String, Duration)> = Vec::new();
+
+    info!("-------------------------------------");
+    info!("Arguments:");
+    info!("Files:   {}", files.join(", "));
+    info!("k:       {}", args.ks.iter().map(usize::to_string).collect::<Vec<String>>().join(","));
+    if args.threads == 0 {
+        info!("Threads: auto");
+    } else {
+        info!("Threads: {}", args.threads);
+    }
+    info!("-------------------------------------");
+
+    // Reading sequences
+    let phase = Instant::now();
+    let read_options = ReadOptions { strict: args.strict, buffer: args.read_buffer, format: args.format, skip_secondary: args.skip_secondary };
+    // Several inputs (e.g. gzipped) are read and decompressed concurrently. Under
+    // automatic scaling the counting pool is not sized yet, reading gets one
+    // thread per file up to the available cores.
+    let read_pool: ThreadPool;
+    let reading_pool: &ThreadPool = match pool {
+        Some(pool) => pool,
+        None => {
+            let available: usize = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+            read_pool = build_pool(files.len().clamp(1, available))?;
+            &read_pool
         }
+    };
+    // --estimate-memory only parses a prefix of the input and extrapolates
+    let mut sampled: f64 = 1.0;
+    let mut inputs: Vec<Vec<Record>> = if args.estimate_memory {
+        let (prefix, fraction) = sample_records(files, &read_options, ESTIMATE_SAMPLE_BASES)?;
+        sampled = fraction;
+        prefix
+    } else {
+        get_records_parallel(files, &read_options, reading_pool)?
+    };
+
+    // From here on all qualities are at offset 33, phred is that of the FASTQ input
+    let bam_inputs: Vec<bool> = files.iter().map(|file| is_bam(file, &read_options)).collect();
+    let phred: PhredOffset = rebase_qualities(&mut inputs, &bam_inputs, args.phred);
 
-        for j in 0..(sequence.len() - k) {
-            *local_map.entry(sequence[j..j + k].to_string()).or_insert(0) += 1;
+    let mut records: Vec<Record> = Vec::new();
+    for file_records in inputs {
+        // The main contig/chromosome of every input
+        if args.longest_only {
+            records.extend(longest_record(file_records));
+        } else {
+            records.extend(file_records);
         }
+    }
 
-        // Merge local HashMap into the global one
-        let mut global_map = kmer_hashmap.lock().unwrap();
-        for (key, value) in local_map {
-            *global_map.entry(key).or_insert(0) += value;
+    // Quality profile of the reads as sequenced, before any trimming
+    if let Some(path) = &args.qual_hist {
+        if records.iter().all(|record| record.quality.is_none()) {
+            return argument_error("--qual-hist needs FASTQ (or BAM) input with quality lines");
         }
-    });
+        save_quality_histogram(&quality_histogram(records.iter().filter_map(|record| record.quality.as_deref()), PhredOffset::Phred33), path)?;
+    }
+
+    // Leading/trailing N runs (e.g. scaffolding gaps)
+    if args.trim_n {
+        records.iter_mut().for_each(trim_record_ns);
+    }
 
-    // Return the final HashMap
-    Mutex::into_inner(kmer_hashmap).unwrap()
+    // Adapter read-through at the 3' end, before quality trimming shortens the reads
+    if let Some(adapter) = &args.adapter {
+        let trimmed: usize = records.iter_mut().map(|record| trim_record_adapter(record, adapter, args.adapter_mismatches)).filter(|&trimmed| trimmed).count();
+        info!("Trimmed the adapter from {} sequences", trimmed);
+        filters.push((String::from("adapter_mismatches"), args.adapter_mismatches as f64));
+    }
+
+    // Quality trimming, with the Phred offset detected from the data if requested
+    if let Some(min_quality) = args.trim_quality {
+        for record in records.iter_mut() {
+            trim_record_quality(record, PhredOffset::Phred33, min_quality);
+        }
+        filters.push((String::from("trim_quality"), min_quality as f64));
+        filters.push((String::from("phred_offset"), phred.value() as f64));
+    }
+
+    // Restrict counting to the BED intervals
+    if let Some(path) = &args.regions {
+        let regions = load_bed(path)?;
+        records = select_regions(records, &regions);
+    }
+
+    // Length QC on the final (trimmed) sequences
+    let mut filtered_sequences: usize = 0;
+    if let Some(min_length) = args.min_seq_len {
+        (records, filtered_sequences) = filter_min_length(records, min_length);
+        info!("Filtered {} sequences shorter than {}", filtered_sequences, min_length);
+        filters.push((String::from("min_seq_len"), min_length as f64));
+    }
+
+    // Quality strings for --qual-weighted, aligned with the sequences
+    let mut qualities: Vec<String> = Vec::new();
+    if args.qual_weighted {
+        if records.iter().any(|record| record.quality.is_none()) {
+            return argument_error("--qual-weighted needs FASTQ input, every read must have a quality line");
+        }
+        qualities = records.iter_mut().filter_map(|record| record.quality.take()).collect();
+    }
+
+    let mut sequences: Vec<String> = records.into_iter().map(|record| record.sequence).collect();
+    let bases: usize = sequences.iter().map(|sequence| sequence.len()).sum();
+
+    // Wrap-around windows of circular sequences
+    if args.circular {
+        sequences = sequences.iter().map(|sequence| circularize(sequence, k)).collect();
+    }
+
+    if args.legacy {
+        sequences.iter_mut().for_each(legacy_windows);
+    }
+    timings.push((String::from("read"), phase.elapsed()));
+
+    info!("Read {} sequences", sequences.len());
+
+    let auto_pool: ThreadPool;
+    let pool: &ThreadPool = match pool {
+        Some(pool) => pool,
+        None => {
+            let available: usize = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+            let threads: usize = auto_threads(sequences.len(), bases, available);
+            debug!("Using {} of {} threads for {} bases", threads, available, bases);
+            auto_pool = build_pool(threads)?;
+            &auto_pool
+        }
+    };
+    info!("-------------------------------------");
+
+    // An empty table is a silent success otherwise, explain it
+    let longest: usize = sequences.iter().map(String::len).max().unwrap_or(0);
+    for &k in args.ks.iter().filter(|&&k| k > longest) {
+        let message: String = format!("no sequence is at least {} bases long (the longest has {}), so there are no k-mers", k, longest);
+        if args.strict {
+            return Err(KmerError::Empty(message));
+        }
+        warn!("Warning: {}, the output will be empty", message);
+    }
+
+    // Projected memory use instead of a run
+    if args.estimate_memory {
+        let phase = Instant::now();
+        // Distinct k-mers grow at most linearly with the input, so for deep read
+        // sets scaling the sample up overestimates
+        if sampled < 1.0 {
+            info!("Estimating from the first {:.1}% of the input", sampled * 100.0);
+        }
+        let distinct_kmers: usize = (estimate_distinct_kmers(&sequences, k, pool, options) / sampled).round() as usize;
+        let bases: usize = (bases as f64 / sampled).round() as usize;
+        let counter_bytes: usize = if args.set { 0 } else { args.count_type.bytes() };
+        let map_bytes: usize = projected_map_bytes(distinct_kmers, k, counter_bytes);
+        let peak_bytes: usize = projected_peak_bytes(distinct_kmers, k, counter_bytes) + bases;
+        let megabytes = |bytes: usize| bytes as f64 / (1024.0 * 1024.0);
+
+        println!("Estimated distinct k-mers: {}", distinct_kmers);
+        println!("Projected k-mer map:       {:.1} MB", megabytes(map_bytes));
+        println!("Sequences in memory:       {:.1} MB", megabytes(bases));
+        println!("Projected peak memory:     {:.1} MB", megabytes(peak_bytes));
+        info!("Estimated in {:?}", phase.elapsed());
+        info!("-------------------------------------");
+        return Ok(false);
+    }
+
+    // Uniform sample of distinct k-mers, in addition to the counts
+    if let Some(size) = args.reservoir {
+        let sample: Vec<String> = reservoir_sample(&sequences, k, size, args.seed, pool, options);
+        let mut file = BufWriter::new(File::create("kmer_sample.txt")?);
+        for kmer in &sample {
+            writeln!(file, "{}", kmer)?;
+        }
+        file.flush()?;
+        info!("Sampled {} k-mers to kmer_sample.txt", sample.len());
+    }
+
+    let distinct_kmers: usize;
+    let total_kmers: Option<u64>;
+
+    if args.qual_weighted {
+
+        // Occurrences weighted by the probability that all their bases are correct
+        let phase = Instant::now();
+        let weighted_hashmap: HashMap<String, f64> = count_weighted_kmers(&sequences, &qualities, k, PhredOffset::Phred33, pool, options);
+        timings.push((String::from("count"), phase.elapsed()));
+
+        distinct_kmers = weighted_hashmap.len();
+        total_kmers = None;
+
+        if !args.no_write {
+            info!("-------------------------------------");
+            info!("Writing weighted kmer counts to file");
+            info!("-------------------------------------");
+
+            let phase = Instant::now();
+            save_weighted(weighted_hashmap, output.unwrap_or(OUTPUT_FILE), args.write_buffer, args.precision, args.sort)?;
+            timings.push((String::from("write"), phase.elapsed()));
+        }
+
+    } else if args.pairs {
+
+        // Adjacent k-mer pairs instead of single k-mers
+        (distinct_kmers, total_kmers) = match args.count_type {
+            CountType::U16 => count_and_write_pairs::<u16>(&sequences, output, pool, options, args, &mut timings)?,
+            CountType::U32 => count_and_write_pairs::<u32>(&sequences, output, pool, options, args, &mut timings)?,
+            CountType::U64 => count_and_write_pairs::<u64>(&sequences, output, pool, options, args, &mut timings)?,
+        };
+
+    } else if args.set {
+
+        // Presence-only counting
+        let phase = Instant::now();
+        let kmer_set: HashSet<String> = count_kmer_set(&sequences, k, pool, options);
+        timings.push((String::from("count"), phase.elapsed()));
+
+        distinct_kmers = kmer_set.len();
+        total_kmers = None;
+
+        if !args.no_write {
+            info!("-------------------------------------");
+            info!("Writing kmer set to file");
+            info!("-------------------------------------");
+
+            let phase = Instant::now();
+            save_kmer_set(kmer_set, output.unwrap_or(OUTPUT_FILE), args.write_buffer, args.sort)?;
+            timings.push((String::from("write"), phase.elapsed()));
+        }
+
+    } else {
+
+        // Kmer counting
+        (distinct_kmers, total_kmers) = count_with_type(&sequences, output, args.hp_both.then_some("raw"), pool, options, args, &mut timings)?;
+
+        // The same counting once more on the homopolymer-compressed sequences,
+        // written next to the regular output
+        if args.hp_both {
+            info!("-------------------------------------");
+            info!("Homopolymer-compressed k-mers");
+            info!("-------------------------------------");
+
+            let compressed: Vec<String> = sequences.iter().map(|sequence| collapse_homopolymers(sequence)).collect();
+            let hp_output: String = hp_path(output.unwrap_or(default_output(args)));
+            let mut hp_timings: Vec<(String, Duration)> = Vec::new();
+            let (hp_distinct, _) = count_with_type(&compressed, Some(&hp_output), Some("hp"), pool, options, args, &mut hp_timings)?;
+            timings.extend(hp_timings.into_iter().map(|(phase, elapsed)| (format!("hp_{}", phase), elapsed)));
+            info!("Distinct homopolymer-compressed k-mers: {}", hp_distinct);
+        }
+    }
+
+    info!("-------------------------------------");
+    info!("Distinct k-mers: {}", distinct_kmers);
+    if let Some(total_kmers) = total_kmers {
+        info!("Total k-mers:    {}", total_kmers);
+    }
+    for (phase, elapsed) in &timings {
+        info!("{:<16} {:?}", format!("{}:", phase), elapsed);
+    }
+    info!("-------------------------------------");
+
+    // Whatever was counted before Ctrl-C or the time limit has been written, but it is incomplete
+    let partial: bool = options.stopped();
+    let partial_reason: Option<String> = if options.interrupted() {
+        warn!("Interrupted: the written output contains PARTIAL results");
+        Some(String::from("interrupted"))
+    } else if partial {
+        warn!("Time limit reached: the written output contains PARTIAL results (partial (time limit))");
+        Some(String::from("time limit"))
+    } else {
+        None
+    };
+
+    if let Some(path) = &args.report {
+        let report = RunReport {
+            input_files: files.to_vec(),
+            k,
+            threads: pool.current_num_threads(),
+            sequences: sequences.len(),
+            bases,
+            filtered_sequences,
+            distinct_kmers,
+            total_kmers,
+            timings,
+            filters,
+            partial,
+            partial_reason,
+        };
+        report.write(path)?;
+    }
+
+    Ok(partial)
 }
 
-fn save_kmers(kmer_hashmap: HashMap<String, u32>) -> io::Result<()> {
+// kmeRS unique: k-mers of the target that are absent (or rare) in all other count files
+fn run_unique(mut args: impl Iterator<Item = String>) -> Result<(), KmerError> {
+
+    let mut inputs: Vec<String> = Vec::new();
+    let mut max_other: u64 = 0;
+    let mut canonical: bool = false;
+    let mut output: String = String::from("kmer_unique.tsv");
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-other" => max_other = parse_value(&mut args, "--max-other")?,
+            "--canonical" => canonical = true,
+            "--output" => output = next_value(&mut args, "--output")?,
+            _ => inputs.push(arg),
+        }
+    }
 
-    let mut file = File::create("kmer_counts.tsv")?;
+    if inputs.len() < 2 {
+        return argument_error(USAGE);
+    }
 
-    for (key, value) in &kmer_hashmap {
-        writeln!(file, "{}\t{}", key, value)?;
+    let mut tables: Vec<HashMap<String, u64>> = Vec::with_capacity(inputs.len());
+    for input in &inputs {
+        let table: HashMap<String, u64> = load_counts(input)?;
+        tables.push(if canonical { canonical_table(table)? } else { table });
     }
 
+    let unique = unique_kmers(&tables[0], &tables[1..], max_other);
+    save_unique(&unique, &output)?;
+
+    info!("Wrote {} k-mers unique to {} to {}", unique.len(), inputs[0], output);
+
     Ok(())
 }
 
-fn main() -> io::Result<()> {
+// kmeRS matrix: sparse k-mer by sample matrix of several count files
+fn run_matrix(mut args: impl Iterator<Item = String>) -> Result<(), KmerError> {
 
-    let start = Instant::now();
+    let mut inputs: Vec<String> = Vec::new();
+    let mut output: String = String::from("kmer_matrix.mtx");
+    let mut index: String = String::from("kmer_matrix_kmers.txt");
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = next_value(&mut args, "--output")?,
+            "--index" => index = next_value(&mut args, "--index")?,
+            _ => inputs.push(arg),
+        }
+    }
 
-    let args: Vec<String> = env::args().collect();
+    if inputs.is_empty() {
+        return argument_error(USAGE);
+    }
 
-    let file: String = String::from(&args[1]);
-    let k: usize  = args[2].parse::<usize>().unwrap();
-    let threads   = args[3].parse::<usize>().unwrap();
+    let matrix: CountMatrix = count_matrix(&inputs)?;
+    save_matrix(&matrix, &output, &index)?;
 
-    println!("-------------------------------------");
-    println!("Arguments:");
-    println!("File:    {}", file);
-    println!("k:       {}", k);
-    println!("Threads: {}", threads);
-    println!("-------------------------------------");
+    info!("Wrote a {} x {} matrix with {} nonzero entries to {} (k-mers in {})", matrix.kmers.len(), matrix.samples.len(), matrix.entries.len(), output, index);
 
-    // Setup for parallel kmer counting
-    ThreadPoolBuilder::new()
-        .num_threads(threads)
-        .build_global()
-        .unwrap();
+    Ok(())
+}
+
+// kmeRS selftest: counts synthetic random sequences, checks the counting
+// invariants and reports the throughput
+fn run_selftest(mut args: impl Iterator<Item = String>) -> Result<(), KmerError> {
 
-    // Kmer counting
-    let kmer_hashmap: HashMap<String, u32> = count_kmers(&file, k);
+    let mut count: usize = 1000;
+    let mut length: usize = 10000;
+    let mut k: usize = 21;
+    let mut threads: usize = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+    let mut seed: u64 = 1;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sequences" => count = parse_value(&mut args, "--sequences")?,
+            "--length" => length = parse_value(&mut args, "--length")?,
+            "--k" => k = parse_value(&mut args, "--k")?,
+            "--threads" => threads = parse_value(&mut args, "--threads")?,
+            "--seed" => seed = parse_value(&mut args, "--seed")?,
+            _ => return argument_error(USAGE),
+        }
+    }
+    if k == 0 || threads == 0 {
+        return argument_error("selftest needs k and threads of at least 1");
+    }
 
-    println!("-------------------------------------");
-    println!("Writing kmer counts to file");
-    println!("-------------------------------------");
+    let pool = build_pool(threads)?;
+    let report: SelftestReport = selftest(count, length, k, seed, &pool)?;
 
-    save_kmers(kmer_hashmap).expect("File writing failed");
+    println!("Self-test passed: {} sequences, {} bases, {} distinct and {} total {}-mers", report.sequences, report.bases, report.distinct_kmers, report.total_kmers, k);
+    println!("Counted in {:?} on {} threads, {:.1} Mbases/s", report.elapsed, threads, report.megabases_per_second());
+
+    Ok(())
+}
+
+// Dispatches the command line (without the program name) to the subcommand or
+// the counting run, every failure surfaces as a KmerError whose exit code main returns
+fn run_command(args: impl Iterator<Item = String>) -> Result<(), KmerError> {
+
+    // -q and -v apply to every subcommand
+    let mut arguments: Vec<String> = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-q" | "--quiet" => set_level(Level::Warn),
+            "-v" | "--verbose" => set_level(Level::Debug),
+            _ => arguments.push(arg),
+        }
+    }
+
+    let mut argv = arguments.into_iter().peekable();
+    match argv.peek().map(String::as_str) {
+        Some("diff") => {
+            argv.next();
+            return run_diff(argv);
+        }
+        Some("merge") => {
+            argv.next();
+            return run_merge(argv);
+        }
+        Some("unique") => {
+            argv.next();
+            return run_unique(argv);
+        }
+        Some("matrix") => {
+            argv.next();
+            return run_matrix(argv);
+        }
+        Some("selftest") => {
+            argv.next();
+            return run_selftest(argv);
+        }
+        Some("--version") | Some("-V") => {
+            println!("{}", version());
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let start = Instant::now();
+
+    let args: Args = parse_args(argv)?;
+    let files: Vec<String> = expand_inputs(&args.files, args.recursive)?;
+
+    let options = CountOptions {
+        bloom_megabytes: if args.bloom { Some(args.bloom_size) } else { None },
+        canonical: args.canonical,
+        stop: Some(install_sigint_handler()),
+        deadline: args.time_limit.map(|seconds| start + Duration::from_secs_f64(seconds)),
+        skip_masked: args.skip_masked,
+        canonical_minimizers: args.canonical_minimizers,
+        minimizer_tiebreak: args.minimizer_tiebreak,
+        ignore_chars: args.ignore_chars.clone(),
+        strict: args.strict,
+        strand: args.strand,
+        alphabet: args.alphabet_file.as_ref().map(|path| Alphabet::load(path)).transpose()?,
+        central_wildcard: args.central_wildcard,
+        progress_every: args.progress_every,
+    };
+
+    if let Some(adapter) = &args.adapter {
+        check_adapter(adapter, options.alphabet.as_ref())?;
+    }
+
+    // Setup for parallel kmer counting
+    let pool: Option<ThreadPool> = if args.threads == 0 { None } else { Some(build_pool(args.threads)?) };
+
+    // With --auto-output every input gets its own table next to it, unless
+    // --combined counts them together (named after the first input)
+    let partial: bool = if args.auto_output && !args.combined {
+        let mut partial: bool = false;
+        for file in &files {
+            let output: String = auto_output(file, &args);
+            partial = run(std::slice::from_ref(file), Some(&output), &args, &options, pool.as_ref())?;
+            if partial {
+                break;
+            }
+        }
+        partial
+    } else {
+        let output: Option<String> = if args.auto_output { files.first().map(|file| auto_output(file, &args)) } else { args.output.clone() };
+        run(&files, output.as_deref(), &args, &options, pool.as_ref())?
+    };
 
     let end = Instant::now();
 
-    println!("DONE after {:?}", end.duration_since(start));
+    info!("DONE after {:?}", end.duration_since(start));
+
+    // A run cut short by --time-limit is partial by request and succeeds
+    if partial && options.interrupted() {
+        std::process::exit(EXIT_INTERRUPTED);
+    }
 
     Ok(())
 
-}
\ No newline at end of file
+}
+
+fn main() {
+    if let Err(error) = run_command(env::args().skip(1)) {
+        eprintln!("Error: {}", error);
+        std::process::exit(error.exit_code());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Path in the temporary directory, unique per test process and name
+    fn temp_path(name: &str) -> String {
+        env::temp_dir().join(format!("kmers_main_test_{}_{}", std::process::id(), name)).display().to_string()
+    }
+
+    fn command(args: &[&str]) -> Result<(), KmerError> {
+        run_command(args.iter().map(|arg| arg.to_string()))
+    }
+
+    // No table is written, the report still has the statistics
+    #[test]
+    fn no_write_skips_the_output_only() {
+        let output: String = temp_path("no_write.tsv");
+        let report: String = temp_path("no_write.json");
+        command(&["fixtures/short.fa", "3", "2", "-q", "--no-write", "--output", &output, "--report", &report]).unwrap();
+        assert!(!Path::new(&output).exists());
+
+        let json: String = fs::read_to_string(&report).unwrap();
+        fs::remove_file(&report).unwrap();
+        assert!(json.contains("\"sequences\": 2,"));
+        assert!(json.contains("\"distinct_kmers\": 4,"));
+        assert!(json.contains("\"total_kmers\": 6,"));
+        assert!(json.contains("\"count\": "));
+    }
+
+    // Every input gets its own table next to it, --combined writes one for all
+    #[test]
+    fn auto_output_writes_one_table_per_input() {
+        let first: String = temp_path("auto_a.fa");
+        let second: String = temp_path("auto_b.fa");
+        fs::copy("fixtures/short.fa", &first).unwrap();
+        fs::copy("fixtures/collapse.fa", &second).unwrap();
+        let first_output: String = temp_path("auto_a.k3.tsv");
+        let second_output: String = temp_path("auto_b.k3.tsv");
+
+        command(&[&first, &second, "3", "2", "-q", "--auto-output"]).unwrap();
+        let first_counts: HashMap<String, u64> = kmers::load_counts(&first_output).unwrap();
+        let second_counts: HashMap<String, u64> = kmers::load_counts(&second_output).unwrap();
+        fs::remove_file(&second_output).unwrap();
+        assert_eq!(first_counts.len(), 4);
+        assert_eq!(first_counts["ACG"], 2);
+        assert_eq!(second_counts.len(), 2);
+        assert_eq!(second_counts["GTA"], 20);
+
+        command(&[&first, &second, "3", "2", "-q", "--auto-output", "--combined"]).unwrap();
+        let combined: HashMap<String, u64> = kmers::load_counts(&first_output).unwrap();
+        fs::remove_file(&first_output).unwrap();
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&second).unwrap();
+        assert!(!Path::new(&second_output).exists());
+        assert_eq!(combined.len(), 5);
+        assert_eq!(combined["GTA"], 21);
+    }
+
+    // The buffer sizes only change how the bytes are moved, not the table
+    #[test]
+    fn buffer_sizes_leave_the_output_unchanged() {
+        let default_output: String = temp_path("buffers_default.tsv");
+        command(&["fixtures/tiny.fq", "5", "2", "-q", "--sort", "lexical", "--output", &default_output]).unwrap();
+        let expected: String = fs::read_to_string(&default_output).unwrap();
+        fs::remove_file(&default_output).unwrap();
+
+        for (read, write) in [("1", "1"), ("1", "4096"), ("4096", "1")] {
+            let output: String = temp_path("buffers.tsv");
+            command(&["fixtures/tiny.fq", "5", "2", "-q", "--sort", "lexical", "--read-buffer", read, "--write-buffer", write, "--output", &output]).unwrap();
+            assert_eq!(fs::read_to_string(&output).unwrap(), expected);
+            fs::remove_file(&output).unwrap();
+        }
+        assert!(matches!(command(&["fixtures/tiny.fq", "5", "2", "-q", "--read-buffer", "0"]), Err(KmerError::Argument(_))));
+    }
+
+    // Counts of the sequences and of their homopolymer-compressed form, next to each other
+    fn hp_both_tables(name: &str, fasta: &str) -> (HashMap<String, u64>, HashMap<String, u64>) {
+        let input: String = temp_path(&format!("{}.fa", name));
+        let output: String = temp_path(&format!("{}.tsv", name));
+        fs::write(&input, fasta).unwrap();
+        command(&[&input, "3", "2", "-q", "--hp-both", "--output", &output]).unwrap();
+        let tables = (kmers::load_counts(&output).unwrap(), kmers::load_counts(&hp_path(&output)).unwrap());
+        for path in [input, hp_path(&output), output] {
+            fs::remove_file(path).unwrap();
+        }
+        tables
+    }
+
+    #[test]
+    fn hp_both_compresses_homopolymers_only() {
+        let (raw, compressed) = hp_both_tables("hp_rich", ">r\nAAACCCGGGT\n");
+        assert_eq!(raw.len(), 8);
+        assert_eq!(raw["AAA"], 1);
+        assert_eq!(compressed, HashMap::from([(String::from("ACG"), 1), (String::from("CGT"), 1)]));
+
+        let (raw, compressed) = hp_both_tables("hp_free", ">r\nACGTACGATC\n");
+        assert_eq!(raw, compressed);
+    }
+
+    // Exit code main reports for a command line, 0 on success
+    fn exit_code(args: &[&str]) -> i32 {
+        command(args).map_or_else(|error| error.exit_code(), |_| 0)
+    }
+
+    #[test]
+    fn errors_exit_with_their_code() {
+        assert_eq!(exit_code(&["fixtures/short.fa", "3", "1", "-q", "--no-write"]), 0);
+        assert_eq!(exit_code(&["fixtures/missing.fa", "3", "1", "-q", "--no-write"]), kmers::EXIT_IO);
+        assert_eq!(exit_code(&["fixtures/short.fa", "three", "1", "-q", "--no-write"]), kmers::EXIT_ARGUMENT);
+        assert_eq!(exit_code(&["fixtures/short.fa", "3", "1", "-q", "--no-write", "--output", "-", "--checksum"]), kmers::EXIT_ARGUMENT);
+        assert_eq!(exit_code(&["fixtures/truncated.fq", "3", "1", "-q", "--no-write"]), kmers::EXIT_INPUT);
+        assert_eq!(exit_code(&["fixtures/duplicate_header.fa", "3", "1", "-q", "--no-write", "--strict"]), kmers::EXIT_INPUT);
+        assert_eq!(exit_code(&["fixtures/short.fa", "21", "1", "-q", "--no-write", "--strict"]), kmers::EXIT_EMPTY);
+        assert_eq!(exit_code(&["fixtures/short.fa", "21", "1", "-q", "--no-write"]), 0);
+    }
+
+    // k = 2 and 3 in one table, with a k column
+    #[test]
+    fn combined_k_output_matches_the_fixture() {
+        let output: String = temp_path("multi_k.tsv");
+        command(&["fixtures/multi_k.fa", "2,3", "2", "-q", "--combined-k-output", "--sort", "lexical", "--output", &output]).unwrap();
+        let written: String = fs::read_to_string(&output).unwrap();
+        fs::remove_file(&output).unwrap();
+        assert_eq!(written, fs::read_to_string("fixtures/multi_k.combined.tsv").unwrap());
+        assert!(written.lines().skip(1).all(|line| line.split('\t').nth(1).unwrap().len().to_string() == line.split('\t').next().unwrap()));
+    }
+
+    // Of the two records of the fixture only ACGT is its own reverse complement
+    #[test]
+    fn palindromes_only_keeps_the_palindromes() {
+        let output: String = temp_path("palindromes.tsv");
+        command(&["fixtures/palindromes.fa", "4", "2", "-q", "--palindromes-only", "--output", &output]).unwrap();
+        let counts: HashMap<String, u64> = kmers::load_counts(&output).unwrap();
+        fs::remove_file(&output).unwrap();
+        assert_eq!(counts, HashMap::from([(String::from("ACGT"), 1)]));
+
+        // Odd k only warns, nothing is kept
+        command(&["fixtures/palindromes.fa", "3", "2", "-q", "--palindromes-only", "--output", &output]).unwrap();
+        assert!(fs::read_to_string(&output).unwrap().is_empty());
+        fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn merge_weights_need_one_factor_per_input() {
+        let output: String = temp_path("merged_weighted.tsv");
+        command(&["merge", "fixtures/merge_a.tsv", "fixtures/merge_b.tsv", "--weights", "1.0,2.0", "--precision", "1", "--output", &output]).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "AAA\t7.0\nACG\t1.0\nCCC\t10.0\n");
+        fs::remove_file(&output).unwrap();
+
+        for weights in ["1.0", "1.0,2.0,3.0", "1.0,-2.0"] {
+            assert!(matches!(command(&["merge", "fixtures/merge_a.tsv", "fixtures/merge_b.tsv", "--weights", weights, "--output", &output]), Err(KmerError::Argument(_))));
+        }
+        assert!(!Path::new(&output).exists());
+    }
+
+    // Both records of short.fa are shorter than 21 bases: an empty table after a
+    // warning, an error that says why under --strict
+    #[test]
+    fn k_beyond_every_sequence_is_explained() {
+        match command(&["fixtures/short.fa", "21", "2", "-q", "--strict", "--no-write"]) {
+            Err(KmerError::Empty(message)) => assert_eq!(message, "no sequence is at least 21 bases long (the longest has 6), so there are no k-mers"),
+            other => panic!("expected an empty input error, got {:?}", other),
+        }
+        assert!(matches!(command(&["fixtures/short.fa", "3,21", "2", "-q", "--strict", "--no-write"]), Err(KmerError::Empty(_))));
+
+        let output: String = temp_path("too_short.tsv");
+        command(&["fixtures/short.fa", "21", "2", "-q", "--output", &output]).unwrap();
+        assert!(fs::read_to_string(&output).unwrap().is_empty());
+        fs::remove_file(&output).unwrap();
+    }
+}
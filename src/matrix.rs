@@ -0,0 +1,91 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::output::load_counts;
+
+// K-mer by sample count matrix with a shared, sorted k-mer index. Only the
+// nonzero cells are stored: a k-mer absent from a sample has no entry.
+pub struct CountMatrix {
+    // Row names
+    pub kmers: Vec<String>,
+    // Column names, the input files in the order given
+    pub samples: Vec<String>,
+    // (row, column, count), 0-based, ordered by row then column
+    pub entries: Vec<(usize, usize, u64)>,
+}
+
+pub fn count_matrix(inputs: &[String]) -> io::Result<CountMatrix> {
+
+    let mut tables: Vec<HashMap<String, u64>> = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        tables.push(load_counts(input)?);
+    }
+
+    let kmers: Vec<String> = tables.iter()
+        .flat_map(|table| table.keys())
+        .collect::<BTreeSet<&String>>()
+        .into_iter().cloned().collect();
+
+    let mut entries: Vec<(usize, usize, u64)> = Vec::new();
+    for (row, kmer) in kmers.iter().enumerate() {
+        for (column, table) in tables.iter().enumerate() {
+            if let Some(&count) = table.get(kmer).filter(|&&count| count > 0) {
+                entries.push((row, column, count));
+            }
+        }
+    }
+
+    Ok(CountMatrix { kmers, samples: inputs.to_vec(), entries })
+}
+
+// MatrixMarket coordinate file (1-based indices, the samples as a comment) and
+// the k-mer of every row, one per line
+pub fn save_matrix(matrix: &CountMatrix, path: &str, index_path: &str) -> io::Result<()> {
+
+    let mut file = BufWriter::new(File::create(path)?);
+
+    writeln!(file, "%%MatrixMarket matrix coordinate integer general")?;
+    writeln!(file, "% rows: k-mers in {}, columns: {}", index_path, matrix.samples.join(" "))?;
+    writeln!(file, "{} {} {}", matrix.kmers.len(), matrix.samples.len(), matrix.entries.len())?;
+    for (row, column, count) in &matrix.entries {
+        writeln!(file, "{} {} {}", row + 1, column + 1, count)?;
+    }
+    file.flush()?;
+
+    let mut index = BufWriter::new(File::create(index_path)?);
+    for kmer in &matrix.kmers {
+        writeln!(index, "{}", kmer)?;
+    }
+    index.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::temp_path;
+
+    // AAA is in two samples, ACG, CCC and GGG in one each
+    #[test]
+    fn fixture_samples_share_one_index() {
+        let inputs: Vec<String> = (1..=3).map(|sample| format!("fixtures/matrix/sample{}.tsv", sample)).collect();
+        let matrix: CountMatrix = count_matrix(&inputs).unwrap();
+        assert_eq!(matrix.kmers, ["AAA", "ACG", "CCC", "GGG"]);
+        assert_eq!(matrix.samples, inputs);
+        assert_eq!(matrix.entries, [(0, 0, 3), (0, 1, 2), (1, 0, 1), (2, 1, 5), (3, 2, 4)]);
+
+        let path: String = temp_path("matrix.mtx");
+        let index_path: String = temp_path("matrix.kmers.txt");
+        save_matrix(&matrix, &path, &index_path).unwrap();
+        let written: String = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines[0], "%%MatrixMarket matrix coordinate integer general");
+        assert_eq!(&lines[2..], ["4 3 5", "1 1 3", "1 2 2", "2 1 1", "3 2 5", "4 3 4"]);
+        assert_eq!(fs::read_to_string(&index_path).unwrap(), "AAA\nACG\nCCC\nGGG\n");
+        for file in [path, index_path] {
+            fs::remove_file(file).unwrap();
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::process;
+
+use crate::counter::Count;
+use crate::error::KmerError;
+use crate::output::{load_counts, Output, DEFAULT_WRITE_BUFFER};
+
+fn sum_overflow(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Merged count overflows u64 at {}", path))
+}
+
+// Sums the tables of all inputs in memory
+pub fn merge_counts(inputs: &[String]) -> io::Result<HashMap<String, u64>> {
+
+    let mut merged: HashMap<String, u64> = HashMap::new();
+
+    for input in inputs {
+        for (key, value) in load_counts(input)? {
+            let count: &mut u64 = merged.entry(key).or_insert(0);
+            *count = count.checked_add(value).ok_or_else(|| sum_overflow(input))?;
+        }
+    }
+    Ok(merged)
+}
+
+// Sums the tables of all inputs scaled by one factor per input, e.g. to
+// normalize samples of different sequencing depth
+pub fn merge_weighted(inputs: &[String], weights: &[f64]) -> io::Result<HashMap<String, f64>> {
+
+    let mut merged: HashMap<String, f64> = HashMap::new();
+
+    for (input, &weight) in inputs.iter().zip(weights) {
+        for (key, value) in load_counts(input)? {
+            *merged.entry(key).or_insert(0.0) += value as f64 * weight;
+        }
+    }
+    Ok(merged)
+}
+
+pub fn save_merged(merged: &HashMap<String, u64>, path: &str) -> io::Result<()> {
+
+    let mut pairs: Vec<(&String, &u64)> = merged.iter().collect();
+    pairs.sort_unstable();
+
+    let mut file = Output::create(path, DEFAULT_WRITE_BUFFER)?;
+    for (key, value) in pairs {
+        writeln!(file, "{}\t{}", key, value)?;
+    }
+    file.flush()
+}
+
+// One sorted input of the k-way merge
+struct SortedInput {
+    path: String,
+    lines: Lines<BufReader<File>>,
+    line_number: usize,
+    previous: Option<String>,
+}
+
+impl SortedInput {
+
+    // Next (kmer, count) pair, checking that the keys really are ascending
+    fn next_pair(&mut self) -> Result<Option<(String, u64)>, KmerError> {
+        loop {
+            let Some(line) = self.lines.next() else {
+                return Ok(None);
+            };
+            let line: String = line?;
+            self.line_number += 1;
+            if line.is_empty() {
+                continue;
+            }
+            let parse_error = |message: String| KmerError::Parse { line: self.line_number, message };
+            let (key, value) = line.split_once('\t')
+                .ok_or_else(|| parse_error(format!("{}: missing tab", self.path)))?;
+            let value: u64 = value.trim().parse::<u64>()
+                .map_err(|_| parse_error(format!("{}: invalid count", self.path)))?;
+            if self.previous.as_deref().is_some_and(|previous| previous >= key) {
+                return Err(parse_error(format!("{} is not sorted by k-mer (write it with --sort lexical)", self.path)));
+            }
+            self.previous = Some(key.to_string());
+            return Ok(Some((key.to_string(), value)));
+        }
+    }
+}
+
+// Streams a k-way merge of count files that are sorted by k-mer, summing equal
+// keys. Only one line per input is held in memory at a time, so the inputs can
+// be far larger than RAM. The output is sorted as well.
+pub fn merge_sorted_files(inputs: &[String], path: &str) -> Result<usize, KmerError> {
+
+    let mut sources: Vec<SortedInput> = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        sources.push(SortedInput {
+            path: input.clone(),
+            lines: BufReader::new(File::open(input)?).lines(),
+            line_number: 0,
+            previous: None,
+        });
+    }
+
+    // Min-heap of the current head of every input
+    let mut heap: BinaryHeap<Reverse<(String, usize, u64)>> = BinaryHeap::new();
+    for (index, source) in sources.iter_mut().enumerate() {
+        if let Some((key, value)) = source.next_pair()? {
+            heap.push(Reverse((key, index, value)));
+        }
+    }
+
+    let mut file = Output::create(path, DEFAULT_WRITE_BUFFER)?;
+    let mut written: usize = 0;
+
+    while let Some(Reverse((key, index, value))) = heap.pop() {
+        let mut total: u64 = value;
+        if let Some((next_key, next_value)) = sources[index].next_pair()? {
+            heap.push(Reverse((next_key, index, next_value)));
+        }
+        // Pull the same key from every other input
+        while heap.peek().is_some_and(|Reverse((other, _, _))| *other == key) {
+            let Reverse((_, other_index, other_value)) = heap.pop().unwrap();
+            total = total.checked_add(other_value).ok_or_else(|| sum_overflow(&sources[other_index].path))?;
+            if let Some((next_key, next_value)) = sources[other_index].next_pair()? {
+                heap.push(Reverse((next_key, other_index, next_value)));
+            }
+        }
+        writeln!(file, "{}\t{}", key, total)?;
+        written += 1;
+    }
+
+    file.flush()?;
+    Ok(written)
+}
+
+// Entries per sorted run of save_kmers_external
+const EXTERNAL_RUN_SIZE: usize = 1 << 20;
+
+// Writes the counts sorted by k-mer without a sorted copy of the whole table:
+// the map is drained into sorted runs of EXTERNAL_RUN_SIZE entries in the temp
+// directory, which are then combined by merge_sorted_files. The output is
+// identical to save_kmers with SortOrder::Lexical.
+pub fn save_kmers_external<C: Count>(kmer_hashmap: HashMap<String, C>, path: &str) -> Result<(), KmerError> {
+    save_kmers_in_runs(kmer_hashmap, path, EXTERNAL_RUN_SIZE)
+}
+
+fn save_kmers_in_runs<C: Count>(kmer_hashmap: HashMap<String, C>, path: &str, run_size: usize) -> Result<(), KmerError> {
+
+    let mut runs: Vec<String> = Vec::new();
+    let mut entries = kmer_hashmap.into_iter();
+
+    loop {
+        let mut run: Vec<(String, C)> = entries.by_ref().take(run_size).collect();
+        if run.is_empty() && !runs.is_empty() {
+            break;
+        }
+        run.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        let run_path: String = env::temp_dir().join(format!("kmers-{}-run{}.tsv", process::id(), runs.len())).to_string_lossy().into_owned();
+        let mut file = BufWriter::new(File::create(&run_path)?);
+        for (key, value) in &run {
+            writeln!(file, "{}\t{}", key, value)?;
+        }
+        file.flush()?;
+        runs.push(run_path);
+
+        if run.len() < run_size {
+            break;
+        }
+    }
+
+    let merged = merge_sorted_files(&runs, path);
+    for run in &runs {
+        let _ = fs::remove_file(run);
+    }
+    merged.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::count::{build_pool, count_kmers, CountOptions};
+    use crate::output::{save_kmers, SortOrder, DEFAULT_WRITE_BUFFER};
+    use crate::selftest::random_sequences;
+    use crate::testing::{temp_file, temp_path};
+
+    // Both merge paths read tables of every --count-type
+    #[test]
+    fn merge_paths_agree_on_u64_counts() {
+        let a: String = temp_file("merge_u64_a.tsv", b"AAA\t4294967296\nCCC\t1\n");
+        let b: String = temp_file("merge_u64_b.tsv", b"AAA\t2\nGGG\t3\n");
+        let inputs: Vec<String> = vec![a.clone(), b.clone()];
+
+        let merged: HashMap<String, u64> = merge_counts(&inputs).unwrap();
+        assert_eq!(merged["AAA"], 4_294_967_298);
+
+        let path: String = temp_path("merge_u64_out.tsv");
+        assert_eq!(merge_sorted_files(&inputs, &path).unwrap(), 3);
+        assert_eq!(load_counts(&path).unwrap(), merged);
+
+        for file in [a, b, path] {
+            fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[test]
+    fn merged_overflow_is_an_error() {
+        let a: String = temp_file("merge_overflow_a.tsv", format!("AAA\t{}\n", u64::MAX).as_bytes());
+        let b: String = temp_file("merge_overflow_b.tsv", b"AAA\t1\n");
+        let inputs: Vec<String> = vec![a.clone(), b.clone()];
+        let path: String = temp_path("merge_overflow_out.tsv");
+
+        assert_eq!(merge_counts(&inputs).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(merge_sorted_files(&inputs, &path), Err(KmerError::Io(_))));
+
+        for file in [a, b, path] {
+            let _ = fs::remove_file(file);
+        }
+    }
+
+    // The three pre-sorted samples of fixtures/matrix, summed and still sorted
+    #[test]
+    fn sorted_fixtures_merge_in_order() {
+        let inputs: Vec<String> = (1..=3).map(|sample| format!("fixtures/matrix/sample{}.tsv", sample)).collect();
+        let path: String = temp_path("merge_sorted_out.tsv");
+        assert_eq!(merge_sorted_files(&inputs, &path).unwrap(), 4);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "AAA\t5\nACG\t1\nCCC\t5\nGGG\t4\n");
+        assert_eq!(load_counts(&path).unwrap(), merge_counts(&inputs).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unsorted_input_is_rejected() {
+        let unsorted: String = temp_file("merge_unsorted.tsv", b"CCC\t1\nAAA\t2\n");
+        let path: String = temp_path("merge_unsorted_out.tsv");
+        let inputs: Vec<String> = vec![String::from("fixtures/merge_a.tsv"), unsorted.clone()];
+        assert!(matches!(merge_sorted_files(&inputs, &path), Err(KmerError::Parse { line: 2, .. })));
+        for file in [unsorted, path] {
+            let _ = fs::remove_file(file);
+        }
+    }
+
+
+    // Runs of 50 entries, so several of them are merged
+    #[test]
+    fn external_sort_matches_the_in_memory_sort() {
+        let sequences: Vec<String> = random_sequences(20, 100, 7);
+        let counts: HashMap<String, u32> = count_kmers(&sequences, 4, &build_pool(2).unwrap(), &CountOptions::default()).unwrap();
+        assert!(counts.len() > 4 * 50);
+
+        let external: String = temp_path("external_sorted.tsv");
+        let in_memory: String = temp_path("in_memory_sorted.tsv");
+        save_kmers_in_runs(counts.clone(), &external, 50).unwrap();
+        save_kmers(counts.clone(), &in_memory, DEFAULT_WRITE_BUFFER, SortOrder::Lexical).unwrap();
+        assert_eq!(fs::read_to_string(&external).unwrap(), fs::read_to_string(&in_memory).unwrap());
+        save_kmers_external(counts, &external).unwrap();
+        assert_eq!(fs::read_to_string(&external).unwrap(), fs::read_to_string(&in_memory).unwrap());
+
+        save_kmers_external(HashMap::<String, u32>::new(), &external).unwrap();
+        assert!(fs::read(&external).unwrap().is_empty());
+        for file in [external, in_memory] {
+            fs::remove_file(file).unwrap();
+        }
+    }
+
+    // AAA: 3 * 1.0 + 2 * 2.0, ACG only in the first and CCC only in the second input
+    #[test]
+    fn weights_scale_each_input() {
+        let inputs: Vec<String> = vec![String::from("fixtures/merge_a.tsv"), String::from("fixtures/merge_b.tsv")];
+        let merged: HashMap<String, f64> = merge_weighted(&inputs, &[1.0, 2.0]).unwrap();
+        assert_eq!(merged, HashMap::from([(String::from("AAA"), 7.0), (String::from("ACG"), 1.0), (String::from("CCC"), 10.0)]));
+        assert_eq!(merge_weighted(&inputs, &[0.5, 0.0]).unwrap()["AAA"], 1.5);
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+
+use crate::error::KmerError;
+use crate::kmer::encode_base;
+
+// Which of several k-mers sharing a window's minimum value is selected
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tiebreak {
+    // The k-mer that starts first, the rule of most minimizer tools
+    #[default]
+    Leftmost,
+    Rightmost,
+}
+
+impl Tiebreak {
+    pub fn parse(value: &str) -> Result<Tiebreak, KmerError> {
+        match value {
+            "leftmost" => Ok(Tiebreak::Leftmost),
+            "rightmost" => Ok(Tiebreak::Rightmost),
+            _ => Err(KmerError::Argument(String::from("--minimizer-tiebreak must be leftmost or rightmost"))),
+        }
+    }
+}
+
+// Canonical 2-bit value of every k-mer (k <= 32), computed with rolling forward
+// and reverse-complement encodings. K-mers containing bases other than A/C/G/T
+// are None.
+fn canonical_values(sequence: &str, k: usize) -> Vec<Option<u64>> {
+
+    let mask: u64 = if k == 32 { u64::MAX } else { (1 << (2 * k)) - 1 };
+    let mut forward: u64 = 0;
+    let mut reverse: u64 = 0;
+    let mut valid: usize = 0;
+
+    let mut values: Vec<Option<u64>> = Vec::with_capacity(sequence.len());
+    for base in sequence.bytes() {
+        match encode_base(base) {
+            Some(code) => {
+                forward = ((forward << 2) | code) & mask;
+                reverse = (reverse >> 2) | ((3 - code) << (2 * (k - 1)));
+                valid += 1;
+            }
+            None => valid = 0,
+        }
+        values.push(if valid >= k { Some(forward.min(reverse)) } else { None });
+    }
+    // values[i] belongs to the k-mer ending at base i, shift to k-mer starts
+    values.drain(..(k - 1).min(values.len()));
+    values
+}
+
+// Canonical minimizers of a sequence: of every w consecutive k-mers, the one
+// with the smallest canonical value is selected, so a k-mer and its reverse
+// complement compete as the same value. Ties between equal values are broken
+// by tiebreak, so the selection is deterministic. A k-mer selected by several
+// overlapping windows is reported once, as (position, canonical value).
+// Sequences with fewer than w k-mers have no minimizers.
+pub fn canonical_minimizers(sequence: &str, k: usize, w: usize, tiebreak: Tiebreak) -> Vec<(usize, u64)> {
+
+    let mut minimizers: Vec<(usize, u64)> = Vec::new();
+    if k == 0 || k > 32 || w == 0 || sequence.len() < k {
+        return minimizers;
+    }
+
+    let values: Vec<Option<u64>> = canonical_values(sequence, k);
+
+    // Positions with increasing values, the front is the window's minimum
+    let mut candidates: VecDeque<(usize, u64)> = VecDeque::new();
+
+    for (position, value) in values.iter().enumerate() {
+        if let Some(value) = *value {
+            // Equal values stay in front of the new k-mer for leftmost, and are
+            // replaced by it for rightmost
+            while candidates.back().is_some_and(|&(_, back)| back > value || (back == value && tiebreak == Tiebreak::Rightmost)) {
+                candidates.pop_back();
+            }
+            candidates.push_back((position, value));
+        }
+        if position + 1 < w {
+            continue;
+        }
+        let window_start: usize = position + 1 - w;
+        while candidates.front().is_some_and(|&(front, _)| front < window_start) {
+            candidates.pop_front();
+        }
+        if let Some(&selected) = candidates.front() {
+            if minimizers.last() != Some(&selected) {
+                minimizers.push(selected);
+            }
+        }
+    }
+    minimizers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CA AA AG GA AA: one window of five 2-mers with the minimum AA at 1 and 4
+    #[test]
+    fn tied_minima_follow_the_tiebreak() {
+        assert_eq!(canonical_minimizers("CAAGAA", 2, 5, Tiebreak::Leftmost), [(1, 0)]);
+        assert_eq!(canonical_minimizers("CAAGAA", 2, 5, Tiebreak::Rightmost), [(4, 0)]);
+
+        // No window of two 2-mers holds a tie, both rules select the same positions
+        assert_eq!(canonical_minimizers("CAAGAA", 2, 2, Tiebreak::Leftmost), [(1, 0), (2, 2), (4, 0)]);
+        assert_eq!(canonical_minimizers("CAAGAA", 2, 2, Tiebreak::Rightmost), [(1, 0), (2, 2), (4, 0)]);
+        assert_eq!(Tiebreak::parse("leftmost").unwrap(), Tiebreak::default());
+        assert!(Tiebreak::parse("random").is_err());
+    }
+}
@@ -0,0 +1,622 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::binary::{is_binary, load_binary};
+use crate::checksum::fnv1a;
+use crate::counter::Count;
+use crate::error::KmerError;
+use crate::kmer::{encode_kmer, reverse_complement};
+use crate::report::json_string;
+use crate::sample::splitmix64;
+
+pub const OUTPUT_FILE: &str = "kmer_counts.tsv";
+
+// Write buffer used unless configured otherwise, the same as BufWriter's default
+pub const DEFAULT_WRITE_BUFFER: usize = 8 * 1024;
+
+// Order of the output lines, unsorted keeps the HashMap's iteration order
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Unsorted,
+    Lexical,
+}
+
+impl SortOrder {
+    pub fn parse(value: &str) -> Result<SortOrder, KmerError> {
+        match value {
+            "none" => Ok(SortOrder::Unsorted),
+            "lexical" => Ok(SortOrder::Lexical),
+            _ => Err(KmerError::Argument(String::from("--sort must be none or lexical"))),
+        }
+    }
+}
+
+// Representation of the count table
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Tsv,
+    Jsonl,
+    // Packed k-mers with varint counts, see binary.rs
+    Binary,
+    // The text dump of Jellyfish (jellyfish dump -c)
+    Jellyfish,
+}
+
+impl OutputFormat {
+    pub fn parse(value: &str) -> Result<OutputFormat, KmerError> {
+        match value {
+            "tsv" => Ok(OutputFormat::Tsv),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "binary" => Ok(OutputFormat::Binary),
+            "jellyfish" => Ok(OutputFormat::Jellyfish),
+            _ => Err(KmerError::Argument(String::from("--format-out must be tsv, jsonl, binary or jellyfish"))),
+        }
+    }
+}
+
+// Output path that writes the table to standard output (--output -)
+pub const STDOUT: &str = "-";
+
+// Lines between two flushes when the output is a named pipe, a device or
+// standard output, so a reader at the other end sees the table while it is written
+const STREAM_FLUSH_LINES: usize = 1000;
+
+// Buffered writer of a count table. Regular files are created (truncated) as
+// usual. An existing FIFO or device is opened for writing as it is, there is
+// nothing to truncate or seek, and flushed every STREAM_FLUSH_LINES lines.
+// STDOUT writes through the standard output handle, appending wherever it
+// points (a pipe, or a file opened with >> by the shell), like a stream.
+pub(crate) struct Output {
+    file: BufWriter<Box<dyn Write>>,
+    stream: bool,
+    // Lines written since the last flush
+    pending: usize,
+}
+
+impl Output {
+    pub(crate) fn create(path: &str, buffer: usize) -> io::Result<Output> {
+        if path == STDOUT {
+            return Ok(Output { file: BufWriter::with_capacity(buffer, Box::new(io::stdout().lock())), stream: true, pending: 0 });
+        }
+        let stream: bool = fs::metadata(path).is_ok_and(|metadata| !metadata.is_file() && !metadata.is_dir());
+        let file: File = if stream { OpenOptions::new().write(true).open(path)? } else { File::create(path)? };
+        Ok(Output { file: BufWriter::with_capacity(buffer, Box::new(file)), stream, pending: 0 })
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let written: usize = self.file.write(bytes)?;
+        if self.stream {
+            self.pending += bytes[..written].iter().filter(|&&byte| byte == b'\n').count();
+            if self.pending >= STREAM_FLUSH_LINES {
+                self.pending = 0;
+                self.file.flush()?;
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+pub(crate) fn ordered<K: Ord, V>(entries: impl Iterator<Item = (K, V)>, sort: SortOrder) -> Vec<(K, V)> {
+    let mut entries: Vec<(K, V)> = entries.collect();
+    if sort == SortOrder::Lexical {
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    }
+    entries
+}
+
+pub fn save_kmers<C: Count>(kmer_hashmap: HashMap<String, C>, path: &str, buffer: usize, sort: SortOrder) -> io::Result<()> {
+
+    let mut file = Output::create(path, buffer)?;
+
+    for (key, value) in ordered(kmer_hashmap.iter(), sort) {
+        writeln!(file, "{}\t{}", key, value)?;
+    }
+
+    file.flush()
+}
+
+// Canonical k-mer, its reverse complement and the count, so canonical counts can
+// be mapped back to either strand
+pub fn save_kmers_both_forms<C: Count>(kmer_hashmap: HashMap<String, C>, path: &str, buffer: usize, sort: SortOrder) -> io::Result<()> {
+
+    let mut file = Output::create(path, buffer)?;
+
+    for (key, value) in ordered(kmer_hashmap.iter(), sort) {
+        writeln!(file, "{}\t{}\t{}", key, reverse_complement(key), value)?;
+    }
+
+    file.flush()
+}
+
+// Writes relative frequencies (count / total count) with a fixed number of decimals
+pub fn save_frequencies<C: Count>(kmer_hashmap: HashMap<String, C>, path: &str, buffer: usize, precision: usize, sort: SortOrder) -> io::Result<()> {
+
+    let mut file = Output::create(path, buffer)?;
+    let total: f64 = kmer_hashmap.values().map(|count| count.to_u64() as f64).sum();
+
+    for (key, value) in ordered(kmer_hashmap.iter(), sort) {
+        writeln!(file, "{}\t{:.*}", key, precision, value.to_u64() as f64 / total)?;
+    }
+
+    file.flush()
+}
+
+pub const JSONL_FILE: &str = "kmer_counts.jsonl";
+
+// Lines after which the JSON-lines output is flushed, so consumers reading the
+// file while it is written see complete records early
+const JSONL_FLUSH_LINES: usize = 10_000;
+
+// One {"kmer": ..., "count": ...} object per line, every line is valid JSON on its own
+pub fn save_jsonl<C: Count>(kmer_hashmap: HashMap<String, C>, path: &str, buffer: usize, sort: SortOrder) -> io::Result<()> {
+
+    let mut file = Output::create(path, buffer)?;
+
+    for (line, (key, value)) in ordered(kmer_hashmap.iter(), sort).into_iter().enumerate() {
+        writeln!(file, "{{\"kmer\": {}, \"count\": {}}}", json_string(key), value)?;
+        if (line + 1).is_multiple_of(JSONL_FLUSH_LINES) {
+            file.flush()?;
+        }
+    }
+
+    file.flush()
+}
+
+// The count tables of several k in one file with a k column, ordered by k
+pub fn save_kmers_combined_k<C: Count>(tables: Vec<(usize, HashMap<String, C>)>, path: &str, buffer: usize, sort: SortOrder) -> io::Result<()> {
+
+    let mut file = Output::create(path, buffer)?;
+
+    writeln!(file, "k\tkmer\tcount")?;
+    for (k, kmer_hashmap) in tables {
+        for (key, value) in ordered(kmer_hashmap.into_iter(), sort) {
+            writeln!(file, "{}\t{}\t{}", k, key, value)?;
+        }
+    }
+
+    file.flush()
+}
+
+pub const JELLYFISH_FILE: &str = "kmer_counts.jf.txt";
+
+// Jellyfish's column layout: k-mer, one space, count, always sorted so the file
+// compares equal to a sorted jellyfish dump -c of the same (canonical) counts
+pub fn save_jellyfish<C: Count>(kmer_hashmap: HashMap<String, C>, path: &str, buffer: usize) -> io::Result<()> {
+
+    let mut file = Output::create(path, buffer)?;
+
+    for (key, value) in ordered(kmer_hashmap.into_iter(), SortOrder::Lexical) {
+        writeln!(file, "{} {}", key, value)?;
+    }
+
+    file.flush()
+}
+
+// K-mers as their 2-bit packing (see encode_kmer) instead of strings, after a
+// "# k=<k>" comment that decode_kmer needs. K-mers with bases other than
+// uppercase A/C/G/T have no encoding and are left out, returns how many.
+pub fn save_kmers_encoded<C: Count>(kmer_hashmap: HashMap<String, C>, path: &str, buffer: usize, k: usize, sort: SortOrder) -> io::Result<usize> {
+
+    let mut file = Output::create(path, buffer)?;
+    let mut skipped: usize = 0;
+
+    writeln!(file, "# k={}", k)?;
+    // Lexical order of the strings is numeric order of the encodings
+    for (key, value) in ordered(kmer_hashmap.into_iter(), sort) {
+        match encode_kmer(&key).filter(|_| key.bytes().all(|base| matches!(base, b'A' | b'C' | b'G' | b'T'))) {
+            Some(encoded) => writeln!(file, "{}\t{}", encoded, value)?,
+            None => skipped += 1,
+        }
+    }
+
+    file.flush()?;
+    Ok(skipped)
+}
+
+pub const FASTA_FILE: &str = "kmer_counts.fa";
+
+// Every distinct k-mer as a FASTA record of its own, numbered in output order
+// with the count in the header: >kmer_1 count=12
+pub fn save_kmers_fasta<C: Count>(kmer_hashmap: HashMap<String, C>, path: &str, buffer: usize, sort: SortOrder) -> io::Result<()> {
+
+    let mut file = Output::create(path, buffer)?;
+
+    for (i, (key, value)) in ordered(kmer_hashmap.into_iter(), sort).into_iter().enumerate() {
+        writeln!(file, ">kmer_{} count={}\n{}", i + 1, value, key)?;
+    }
+
+    file.flush()
+}
+
+pub const DEBRUIJN_FILE: &str = "kmer_debruijn.tsv";
+
+// Output path next to the input with k in the name: reads.fq -> reads.k21.tsv.
+// A .gz suffix is stripped first, reads.fq.gz gives reads.k21.tsv as well.
+pub fn auto_output_path(input: &str, k: usize) -> String {
+    let path = Path::new(input.strip_suffix(".gz").unwrap_or(input));
+    let stem: String = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+    path.with_file_name(format!("{}.k{}.tsv", stem, k)).to_string_lossy().into_owned()
+}
+
+// De Bruijn graph edge list: every k-mer is an edge from its (k-1)-prefix to
+// its (k-1)-suffix, weighted by the k-mer's count
+pub fn save_debruijn<C: Count>(kmer_hashmap: HashMap<String, C>, path: &str, buffer: usize, sort: SortOrder) -> io::Result<()> {
+
+    let mut file = Output::create(path, buffer)?;
+
+    writeln!(file, "from\tto\tweight")?;
+    for (key, value) in ordered(kmer_hashmap.iter(), sort) {
+        if key.len() < 2 {
+            continue;
+        }
+        writeln!(file, "{}\t{}\t{}", &key[..key.len() - 1], &key[1..], value)?;
+    }
+
+    file.flush()
+}
+
+// Reads a kmer<TAB>count table as written by save_kmers, or a binary one
+// (--format-out binary). Counts are read as u64, so tables of every
+// --count-type can be read back.
+pub fn load_counts(path: &str) -> io::Result<HashMap<String, u64>> {
+
+    if is_binary(path)? {
+        return load_binary(path);
+    }
+
+    let reader = BufReader::new(File::open(path)?);
+    let mut kmer_hashmap: HashMap<String, u64> = HashMap::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let (key, value) = line.split_once('\t')
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Missing tab in line: {}", line)))?;
+        let value: u64 = value.trim().parse::<u64>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid count in line: {}", line)))?;
+        let count: &mut u64 = kmer_hashmap.entry(key.to_string()).or_insert(0);
+        *count = count.checked_add(value)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Count of {} overflows u64 in {}", key, path)))?;
+    }
+
+    Ok(kmer_hashmap)
+}
+
+pub fn save_kmer_set(kmer_set: HashSet<String>, path: &str, buffer: usize, sort: SortOrder) -> io::Result<()> {
+
+    let mut file = Output::create(path, buffer)?;
+
+    for (key, _) in ordered(kmer_set.iter().map(|key| (key, ())), sort) {
+        writeln!(file, "{}", key)?;
+    }
+
+    file.flush()
+}
+
+// Inserts a tag before the extension: kmer_counts.tsv -> kmer_counts.<tag>.tsv
+fn tagged_path(path: &str, tag: &str) -> String {
+    let path = Path::new(path);
+    let stem: String = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+    let name: String = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, tag, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, tag),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+// kmer_counts.tsv -> kmer_counts.shard3.tsv
+pub fn shard_path(path: &str, shard: usize) -> String {
+    tagged_path(path, &format!("shard{}", shard))
+}
+
+// Counterpart of an output for the homopolymer-compressed sequences: kmer_counts.tsv -> kmer_counts.hp.tsv
+pub fn hp_path(path: &str) -> String {
+    tagged_path(path, "hp")
+}
+
+// Output of one k when several are counted: kmer_counts.tsv -> kmer_counts.k21.tsv
+pub fn k_path(path: &str, k: usize) -> String {
+    tagged_path(path, &format!("k{}", k))
+}
+
+// FNV-1a of the k-mer, mixed by splitmix64 so the low bits taken by the modulo
+// depend on every base. Unlike DefaultHasher (not guaranteed across Rust
+// releases), the routing is fixed, shards of different runs and builds line up.
+pub fn shard_of(key: &str, shards: usize) -> usize {
+    (splitmix64(fnv1a(key.as_bytes())) % shards as u64) as usize
+}
+
+// Writes one file per shard, every k-mer always lands in the same shard
+pub fn save_kmers_split<C: Count>(kmer_hashmap: HashMap<String, C>, path: &str, buffer: usize, shards: usize, sort: SortOrder) -> io::Result<()> {
+
+    let mut files: Vec<Output> = Vec::with_capacity(shards);
+    for shard in 0..shards {
+        files.push(Output::create(&shard_path(path, shard), buffer)?);
+    }
+
+    for (key, value) in ordered(kmer_hashmap.iter(), sort) {
+        writeln!(files[shard_of(key, shards)], "{}\t{}", key, value)?;
+    }
+
+    for file in files.iter_mut() {
+        file.flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::count::{build_pool, count_kmer_set, count_kmers, CountOptions};
+    use crate::kmer::decode_kmer;
+    use crate::sequences::{get_records, ReadOptions, Record};
+    use crate::testing::{temp_file, temp_path, Json};
+
+    // A consumer on a named pipe sees the first STREAM_FLUSH_LINES lines before
+    // the writer is done (or flushes at the end)
+    #[cfg(unix)]
+    #[test]
+    fn named_pipe_is_flushed_while_writing() {
+        use std::os::unix::fs::FileTypeExt;
+        use std::sync::mpsc;
+        use std::thread;
+        use std::time::Duration;
+
+        let path: String = temp_path("fifo");
+        assert!(std::process::Command::new("mkfifo").arg(&path).status().unwrap().success());
+
+        let (sender, receiver) = mpsc::channel::<usize>();
+        let reader_path: String = path.clone();
+        let reader = thread::spawn(move || {
+            let mut lines = BufReader::new(File::open(&reader_path).unwrap()).lines();
+            for read in 1..=STREAM_FLUSH_LINES {
+                lines.next().unwrap().unwrap();
+                if read == STREAM_FLUSH_LINES {
+                    sender.send(read).unwrap();
+                }
+            }
+            lines.count()
+        });
+
+        let mut output: Output = Output::create(&path, DEFAULT_WRITE_BUFFER).unwrap();
+        for line in 0..STREAM_FLUSH_LINES {
+            writeln!(output, "AAA\t{}", line).unwrap();
+        }
+        // Still open and not flushed explicitly
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok(STREAM_FLUSH_LINES));
+        writeln!(output, "CCC\t1").unwrap();
+        drop(output);
+
+        assert_eq!(reader.join().unwrap(), 1);
+        assert!(fs::metadata(&path).unwrap().file_type().is_fifo());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn u64_counts_round_trip() {
+        let path: String = temp_path("u64_round_trip.tsv");
+        let counts: HashMap<String, u64> = HashMap::from([(String::from("AAA"), 4_294_967_296), (String::from("ACG"), u64::MAX)]);
+        save_kmers(counts.clone(), &path, DEFAULT_WRITE_BUFFER, SortOrder::Lexical).unwrap();
+        assert_eq!(load_counts(&path).unwrap(), counts);
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Summing the lines of a k-mer beyond u64 is an error, not a panic
+    #[test]
+    fn summed_counts_do_not_overflow() {
+        let path: String = temp_file("summed.tsv", b"AAA\t4294967295\nAAA\t1\n");
+        assert_eq!(load_counts(&path).unwrap()["AAA"], 4_294_967_296);
+        fs::write(&path, format!("AAA\t{}\nAAA\t1\n", u64::MAX)).unwrap();
+        assert_eq!(load_counts(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn auto_output_path_strips_gz() {
+        assert_eq!(auto_output_path("fixtures/tiny.fq", 3), "fixtures/tiny.k3.tsv");
+        assert_eq!(auto_output_path("fixtures/tiny.fq.gz", 3), "fixtures/tiny.k3.tsv");
+        assert_eq!(auto_output_path("reads.gz", 21), "reads.k21.tsv");
+    }
+
+    // The routing is a fixed function of the k-mer, the same for every run and build
+    #[test]
+    fn shards_are_stable() {
+        assert_eq!(shard_of("AAA", 4), 1);
+        assert_eq!(shard_of("ACG", 4), 3);
+        assert_eq!(shard_of("CCC", 4), 0);
+        assert_eq!(shard_of("ACGTACGTACGTACGTACGTA", 7), 5);
+    }
+
+    // Every k-mer is written once, to the shard shard_of names
+    #[test]
+    fn split_routes_every_kmer_to_its_shard() {
+        let sequences: Vec<String> = tiny_sequences();
+        let counts: HashMap<String, u64> = count_kmers(&sequences, 5, &build_pool(2).unwrap(), &CountOptions::default()).unwrap();
+        let path: String = temp_path("split.tsv");
+        save_kmers_split(counts.clone(), &path, DEFAULT_WRITE_BUFFER, 3, SortOrder::Lexical).unwrap();
+
+        let mut union: HashMap<String, u64> = HashMap::new();
+        for shard in 0..3 {
+            let shard_file: String = shard_path(&path, shard);
+            let shard_counts: HashMap<String, u64> = load_counts(&shard_file).unwrap();
+            assert!(!shard_counts.is_empty());
+            assert!(shard_counts.keys().all(|key| shard_of(key, 3) == shard));
+            union.extend(shard_counts);
+            fs::remove_file(shard_file).unwrap();
+        }
+        assert_eq!(union, counts);
+    }
+
+
+    fn tiny_sequences() -> Vec<String> {
+        get_records(&"fixtures/tiny.fq".to_string(), &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect()
+    }
+
+    // One distinct k-mer per line, the keys of the count table, no count column
+    #[test]
+    fn set_output_lists_the_distinct_kmers() {
+        let sequences: Vec<String> = tiny_sequences();
+        let pool = build_pool(2).unwrap();
+        for options in [CountOptions::default(), CountOptions { canonical: true, ..CountOptions::default() }] {
+            let counts: HashMap<String, u32> = count_kmers(&sequences, 5, &pool, &options).unwrap();
+            let set: HashSet<String> = count_kmer_set(&sequences, 5, &pool, &options);
+            assert_eq!(set, counts.keys().cloned().collect::<HashSet<String>>());
+
+            let path: String = temp_path("set.txt");
+            save_kmer_set(set, &path, DEFAULT_WRITE_BUFFER, SortOrder::Lexical).unwrap();
+            let written: String = fs::read_to_string(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            let lines: Vec<&str> = written.lines().collect();
+            let mut expected: Vec<&str> = counts.keys().map(String::as_str).collect();
+            expected.sort_unstable();
+            assert_eq!(lines, expected);
+            assert!(lines.iter().all(|line| line.len() == 5 && !line.contains('\t')));
+        }
+    }
+
+
+    // 3 / 7, 2 / 7, 1 / 7 and 1 / 7 with exactly three decimals each
+    #[test]
+    fn frequencies_use_the_precision() {
+        let counts: HashMap<String, u64> = load_counts("fixtures/legacy.k3.tsv").unwrap();
+        let path: String = temp_path("frequencies.tsv");
+        save_frequencies(counts.clone(), &path, DEFAULT_WRITE_BUFFER, 3, SortOrder::Lexical).unwrap();
+        let written: String = fs::read_to_string(&path).unwrap();
+        assert_eq!(written, "ACG\t0.429\nCGT\t0.286\nGTA\t0.143\nTAC\t0.143\n");
+        assert!(written.lines().all(|line| line.split('\t').nth(1).unwrap().split('.').nth(1).unwrap().len() == 3));
+
+        save_frequencies(counts, &path, DEFAULT_WRITE_BUFFER, 0, SortOrder::Lexical).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ACG\t0\nCGT\t0\nGTA\t0\nTAC\t0\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+
+    // Every k-mer is an edge from its (k-1)-prefix to its (k-1)-suffix
+    #[test]
+    fn debruijn_edges_carry_the_counts() {
+        let counts: HashMap<String, u32> = HashMap::from([(String::from("ACG"), 3), (String::from("CGT"), 2), (String::from("GTA"), 1)]);
+        let path: String = temp_path("debruijn.tsv");
+        save_debruijn(counts, &path, DEFAULT_WRITE_BUFFER, SortOrder::Lexical).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "from\tto\tweight\nAC\tCG\t3\nCG\tGT\t2\nGT\tTA\t1\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Every line is a JSON object of its own, together they are the table
+    #[test]
+    fn jsonl_lines_parse_independently() {
+        let mut counts: HashMap<String, u32> = count_kmers(&tiny_sequences(), 5, &build_pool(2).unwrap(), &CountOptions::default()).unwrap();
+        counts.insert(String::from("A\"C\\G"), 7);
+        let path: String = temp_path("counts.jsonl");
+        save_jsonl(counts.clone(), &path, DEFAULT_WRITE_BUFFER, SortOrder::Lexical).unwrap();
+        let written: String = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut read: Vec<(String, u32)> = Vec::new();
+        for line in written.lines() {
+            let record: Json = Json::parse(line);
+            assert_eq!(record.keys(), ["kmer", "count"]);
+            match (record.get("kmer"), record.get("count")) {
+                (Json::String(kmer), Json::Number(count)) => read.push((kmer.clone(), *count as u32)),
+                other => panic!("unexpected record {:?}", other),
+            }
+        }
+        assert_eq!(read.len(), counts.len());
+        assert!(read.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(read.into_iter().collect::<HashMap<String, u32>>(), counts);
+    }
+
+    // Space-separated, canonical and sorted like a sorted jellyfish dump -c
+    #[test]
+    fn jellyfish_dump_matches_the_fixture() {
+        let expected: String = fs::read_to_string("fixtures/jellyfish.k3.jf.txt").unwrap();
+        let options: CountOptions = CountOptions { canonical: true, ..CountOptions::default() };
+        for input in ["fixtures/jellyfish.fa", "fixtures/jellyfish.fa.gz"] {
+            let sequences: Vec<String> = get_records(&input.to_string(), &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect();
+            let counts: HashMap<String, u32> = count_kmers(&sequences, 3, &build_pool(2).unwrap(), &options).unwrap();
+            let path: String = temp_path("dump.jf.txt");
+            save_jellyfish(counts, &path, DEFAULT_WRITE_BUFFER).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    // Second column: the first one read backwards on the other strand
+    #[test]
+    fn both_forms_pair_each_canonical_kmer_with_its_reverse_complement() {
+        let sequences: Vec<String> = get_records(&"fixtures/jellyfish.fa".to_string(), &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect();
+        let counts: HashMap<String, u32> = count_kmers(&sequences, 3, &build_pool(2).unwrap(), &CountOptions { canonical: true, ..CountOptions::default() }).unwrap();
+        let path: String = temp_path("both_forms.tsv");
+        save_kmers_both_forms(counts.clone(), &path, DEFAULT_WRITE_BUFFER, SortOrder::Lexical).unwrap();
+        let written: String = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(written.starts_with("AAC\tGTT\t2\n"));
+        assert_eq!(written.lines().count(), counts.len());
+        for line in written.lines() {
+            let columns: Vec<&str> = line.split('\t').collect();
+            let complement: String = columns[0].chars().rev().map(|base| match base { 'A' => 'T', 'C' => 'G', 'G' => 'C', _ => 'A' }).collect();
+            assert_eq!(columns[1], complement);
+            assert!(columns[0] <= columns[1]);
+            assert_eq!(columns[2].parse::<u32>().unwrap(), counts[columns[0]]);
+        }
+    }
+
+    // Every integer decodes back to its k-mer under the k of the header comment,
+    // the four windows over the N of the fixture have no encoding
+    #[test]
+    fn encoded_output_round_trips() {
+        let sequences: Vec<String> = get_records(&"fixtures/encode.fa".to_string(), &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect();
+        let mut counts: HashMap<String, u32> = count_kmers(&sequences, 4, &build_pool(2).unwrap(), &CountOptions::default()).unwrap();
+        let path: String = temp_path("encoded.tsv");
+        assert_eq!(save_kmers_encoded(counts.clone(), &path, DEFAULT_WRITE_BUFFER, 4, SortOrder::Lexical).unwrap(), 4);
+        let written: String = fs::read_to_string(&path).unwrap();
+        assert_eq!(written, fs::read_to_string("fixtures/encode.k4.tsv").unwrap());
+
+        let mut lines = written.lines();
+        let k: usize = lines.next().unwrap().strip_prefix("# k=").unwrap().parse().unwrap();
+        let decoded: HashMap<String, u32> = lines.map(|line| {
+            let (encoded, count) = line.split_once('\t').unwrap();
+            (decode_kmer(encoded.parse().unwrap(), k), count.parse().unwrap())
+        }).collect();
+        counts.retain(|kmer, _| !kmer.contains('N'));
+        assert_eq!(decoded, counts);
+
+        // Soft-masked k-mers have no encoding either
+        counts.insert(String::from("acgt"), 1);
+        assert_eq!(save_kmers_encoded(counts, &path, DEFAULT_WRITE_BUFFER, 4, SortOrder::Lexical).unwrap(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    // One record per distinct canonical k-mer, the count in its header
+    #[test]
+    fn fasta_output_matches_the_fixture() {
+        let sequences: Vec<String> = get_records(&"fixtures/jellyfish.fa".to_string(), &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect();
+        let counts: HashMap<String, u32> = count_kmers(&sequences, 3, &build_pool(2).unwrap(), &CountOptions { canonical: true, ..CountOptions::default() }).unwrap();
+        let path: String = temp_path("kmers.fa");
+        save_kmers_fasta(counts.clone(), &path, DEFAULT_WRITE_BUFFER, SortOrder::Lexical).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), fs::read_to_string("fixtures/jellyfish.k3.canonical.fa").unwrap());
+
+        // Read back as FASTA, the headers carry the counts
+        let records: Vec<Record> = get_records(&path, &ReadOptions::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), counts.len());
+        for record in records {
+            let count: u32 = record.id.split_once(" count=").unwrap().1.parse().unwrap();
+            assert_eq!(counts[&record.sequence], count);
+        }
+    }
+
+}
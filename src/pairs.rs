@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use rayon::{ThreadPool, prelude::*};
+
+use crate::count::{add_count, kmer_key, kmer_windows, overflow_error, overflow_stop, split_into_tasks, CountOptions};
+use crate::counter::Count;
+use crate::error::KmerError;
+use crate::output::{ordered, Output, SortOrder};
+
+pub const PAIRS_FILE: &str = "kmer_pairs.tsv";
+
+// Two k-mers that follow each other in a sequence (start positions i and i + 1)
+pub type KmerPair = (String, String);
+
+// Counts adjacent k-mer pairs: every window of k + 1 bases yields the pair of its
+// first and last k bases, each under its counting key (e.g. canonical). A
+// sequence of length L contributes L - k pairs. The pair space grows much faster
+// than the k-mer space, so expect several times the memory of plain counting.
+// Counters of type C saturate (or fail under strict) like those of count_kmers.
+pub fn count_pairs<C: Count>(sequences: &[String], k: usize, pool: &ThreadPool, options: &CountOptions) -> Result<HashMap<KmerPair, C>, KmerError> {
+
+    let pair_hashmap: Mutex<HashMap<KmerPair, C>> = Mutex::new(HashMap::new());
+    let overflowed = AtomicBool::new(false);
+
+    // Pieces overlap by k bases, so every (k + 1)-window lies in exactly one piece
+    let tasks: Vec<(bool, &str)> = split_into_tasks(sequences, k + 1, options);
+
+    pool.install(|| {
+        tasks.par_iter().for_each(|&(_, sequence)| {
+
+            if options.stopped() || overflow_stop(&overflowed, options) {
+                return;
+            }
+
+            let mut local_map: HashMap<KmerPair, C> = HashMap::new();
+            for window in kmer_windows(sequence, k + 1, options) {
+                let pair: KmerPair = (kmer_key(&window[..k], k, options).into_owned(), kmer_key(&window[1..], k, options).into_owned());
+                add_count(local_map.entry(pair).or_default(), C::ONE, &overflowed, options);
+            }
+
+            let mut global_map = pair_hashmap.lock().unwrap();
+            for (pair, value) in local_map {
+                add_count(global_map.entry(pair).or_default(), value, &overflowed, options);
+            }
+        });
+    });
+
+    overflow_error::<C>(&overflowed, options)?;
+    Ok(Mutex::into_inner(pair_hashmap).unwrap())
+}
+
+// kmer1, kmer2 and count per pair, with a header like the De Bruijn edge list
+pub fn save_pairs<C: Count>(pair_hashmap: HashMap<KmerPair, C>, path: &str, buffer: usize, sort: SortOrder) -> io::Result<()> {
+
+    let mut file = Output::create(path, buffer)?;
+
+    writeln!(file, "kmer1\tkmer2\tcount")?;
+    for ((first, second), count) in ordered(pair_hashmap.into_iter(), sort) {
+        writeln!(file, "{}\t{}\t{}", first, second, count)?;
+    }
+
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::count::build_pool;
+    use crate::output::DEFAULT_WRITE_BUFFER;
+    use crate::testing::temp_path;
+
+    fn pair(first: &str, second: &str) -> KmerPair {
+        (first.to_string(), second.to_string())
+    }
+
+    // ACGACGA has the windows ACG CGA GAC ACG CGA, four adjacent pairs
+    #[test]
+    fn adjacent_kmers_are_paired() {
+        let sequences: Vec<String> = vec![String::from("ACGACGA"), String::from("ACG")];
+        let pairs: HashMap<KmerPair, u32> = count_pairs(&sequences, 3, &build_pool(2).unwrap(), &CountOptions::default()).unwrap();
+        assert_eq!(pairs, HashMap::from([(pair("ACG", "CGA"), 2), (pair("CGA", "GAC"), 1), (pair("GAC", "ACG"), 1)]));
+
+        let path: String = temp_path("pairs.tsv");
+        save_pairs(pairs, &path, DEFAULT_WRITE_BUFFER, SortOrder::Lexical).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "kmer1\tkmer2\tcount\nACG\tCGA\t2\nCGA\tGAC\t1\nGAC\tACG\t1\n");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+use crate::alphabet::Alphabet;
+use crate::error::KmerError;
+use crate::sequences::Record;
+
+// Drops records shorter than min_length, returns the kept records and the number dropped
+pub fn filter_min_length(records: Vec<Record>, min_length: usize) -> (Vec<Record>, usize) {
+    let total: usize = records.len();
+    let kept: Vec<Record> = records.into_iter().filter(|record| record.sequence.len() >= min_length).collect();
+    let dropped: usize = total - kept.len();
+    (kept, dropped)
+}
+
+// Keeps only the longest record (the first of several equally long ones), e.g.
+// the main chromosome of an assembly
+pub fn longest_record(records: Vec<Record>) -> Vec<Record> {
+    let mut longest: Option<Record> = None;
+    for record in records {
+        if longest.as_ref().is_none_or(|longest| record.sequence.len() > longest.sequence.len()) {
+            longest = Some(record);
+        }
+    }
+    longest.into_iter().collect()
+}
+
+fn is_n(base: char) -> bool {
+    base == 'N' || base == 'n'
+}
+
+// Strips leading and trailing runs of N/n, internal Ns are kept
+pub fn trim_ns(sequence: &str) -> &str {
+    sequence.trim_matches(is_n)
+}
+
+// trim_ns on a whole record, keeping the quality string aligned with the sequence
+pub fn trim_record_ns(record: &mut Record) {
+    let start: usize = record.sequence.len() - record.sequence.trim_start_matches(is_n).len();
+    let end: usize = record.sequence.trim_end_matches(is_n).len().max(start);
+    if start == 0 && end == record.sequence.len() {
+        return;
+    }
+    record.sequence = record.sequence[start..end].to_string();
+    if let Some(quality) = &record.quality {
+        record.quality = Some(quality[start.min(quality.len())..end.min(quality.len())].to_string());
+    }
+}
+
+// Collapses every run of the same base into a single base (case-insensitive,
+// the first base of a run is kept): AAACGGT -> ACGT
+pub fn collapse_homopolymers(sequence: &str) -> String {
+    let mut collapsed: String = String::with_capacity(sequence.len());
+    let mut previous: Option<char> = None;
+    for base in sequence.chars() {
+        if previous.is_some_and(|previous| previous.eq_ignore_ascii_case(&base)) {
+            continue;
+        }
+        collapsed.push(base);
+        previous = Some(base);
+    }
+    collapsed
+}
+
+// Appends the first k - 1 bases to the end of a circular sequence (plasmid,
+// circular genome), which adds the k - 1 windows that wrap around its origin.
+// Sequences shorter than k are left unchanged.
+pub fn circularize(sequence: &str, k: usize) -> String {
+    if k < 2 || sequence.len() < k {
+        return sequence.to_string();
+    }
+    match sequence.get(..k - 1) {
+        Some(prefix) => format!("{}{}", sequence, prefix),
+        None => sequence.to_string(),
+    }
+}
+
+// --legacy: the original counter looped over 0..len - k and never counted the
+// last window of a sequence. Dropping the last base removes exactly that window,
+// the only one containing it; sequences of at most k bases count nothing.
+pub fn legacy_windows(sequence: &mut String) {
+    sequence.pop();
+}
+
+// Default mismatches tolerated by --adapter
+pub const DEFAULT_ADAPTER_MISMATCHES: usize = 1;
+
+// Shorter partial matches at the 3' end are too likely to occur by chance
+const MIN_ADAPTER_OVERLAP: usize = 3;
+
+// The adapter is compared with the reads (ignoring case), so apart from the
+// wildcard N it has to use their alphabet
+pub fn check_adapter(adapter: &str, alphabet: Option<&Alphabet>) -> Result<(), KmerError> {
+    let valid = |base: char| base.eq_ignore_ascii_case(&'N') || match alphabet {
+        Some(alphabet) => base.is_ascii() && (alphabet.contains(base.to_ascii_uppercase() as u8) || alphabet.contains(base.to_ascii_lowercase() as u8)),
+        None => "ACGT".contains(base.to_ascii_uppercase()),
+    };
+    match adapter.chars().find(|&base| !valid(base)) {
+        Some(base) => {
+            let alphabet: &str = if alphabet.is_some() { "the --alphabet-file alphabet" } else { "A/C/G/T/N" };
+            Err(KmerError::Argument(format!("--adapter contains '{}', which is not in {}", base, alphabet)))
+        }
+        None => Ok(()),
+    }
+}
+
+// Cuts an adapter and everything after it from a read: the leftmost position
+// where the adapter occurs, or where the rest of the read is a prefix of the
+// adapter (a partial 3' match of at least MIN_ADAPTER_OVERLAP bases). Bases are
+// compared case-insensitively, an N in the adapter matches any base, with up to
+// max_mismatch mismatches for a full match and proportionally fewer for a
+// partial one. A lightweight trimmer for counting, not a replacement for a
+// dedicated adapter trimming tool.
+pub fn trim_adapter<'a>(read: &'a str, adapter: &str, max_mismatch: usize) -> &'a str {
+
+    let read_bytes: &[u8] = read.as_bytes();
+    let adapter_bytes: &[u8] = adapter.as_bytes();
+    if adapter_bytes.is_empty() {
+        return read;
+    }
+
+    for start in 0..read_bytes.len() {
+        let overlap: usize = (read_bytes.len() - start).min(adapter_bytes.len());
+        if overlap < MIN_ADAPTER_OVERLAP.min(adapter_bytes.len()) {
+            break;
+        }
+        let allowed: usize = max_mismatch * overlap / adapter_bytes.len();
+        let mismatches: usize = read_bytes[start..start + overlap].iter()
+            .zip(adapter_bytes)
+            .filter(|&(base, &expected)| !expected.eq_ignore_ascii_case(&b'N') && !base.eq_ignore_ascii_case(&expected))
+            .count();
+        if mismatches <= allowed && read.is_char_boundary(start) {
+            return &read[..start];
+        }
+    }
+    read
+}
+
+// trim_adapter on a whole record, keeping the quality string aligned with the
+// sequence. Returns whether the record was trimmed.
+pub fn trim_record_adapter(record: &mut Record, adapter: &str, max_mismatch: usize) -> bool {
+    let end: usize = trim_adapter(&record.sequence, adapter, max_mismatch).len();
+    if end == record.sequence.len() {
+        return false;
+    }
+    record.sequence.truncate(end);
+    if let Some(quality) = &mut record.quality {
+        quality.truncate(end);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::count::{build_pool, count_kmers, CountOptions};
+    use crate::output::load_counts;
+    use crate::sequences::{get_records, ReadOptions};
+
+    fn fixture_sequences(name: &str) -> Vec<String> {
+        get_records(&format!("fixtures/{}", name), &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect()
+    }
+
+    fn counts(sequences: &[String]) -> HashMap<String, u64> {
+        count_kmers(sequences, 3, &build_pool(1).unwrap(), &CountOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn legacy_counts_match_fixtures() {
+        let mut sequences: Vec<String> = fixture_sequences("legacy.fa");
+        assert_eq!(counts(&sequences), load_counts("fixtures/legacy.k3.tsv").unwrap());
+        sequences.iter_mut().for_each(legacy_windows);
+        assert_eq!(counts(&sequences), load_counts("fixtures/legacy.k3.legacy.tsv").unwrap());
+    }
+
+    // fixtures/adapter.fq: r1 has the full adapter, r2 a 6-base partial match at
+    // its 3' end, r3 the adapter with one mismatch (T for A at the third base)
+    #[test]
+    fn adapter_is_trimmed_from_fixture_reads() {
+        let mut records: Vec<Record> = get_records(&"fixtures/adapter.fq".to_string(), &ReadOptions::default()).unwrap();
+        let trimmed: Vec<bool> = records.iter_mut().map(|record| trim_record_adapter(record, "AGATCGGAAGAGC", 1)).collect();
+        assert_eq!(trimmed, vec![true, true, true]);
+        let sequences: Vec<&str> = records.iter().map(|record| record.sequence.as_str()).collect();
+        assert_eq!(sequences, vec!["ACGTTGCAACGGATC", "TTTTGGGGCCCCAAAA", "ACGTACGTACGT"]);
+        assert!(records.iter().all(|record| record.quality.as_ref().map(|quality| quality.len()) == Some(record.sequence.len())));
+    }
+
+    // None of the adapter's k-mers is left once the reads are trimmed
+    #[test]
+    fn adapter_kmers_disappear() {
+        let adapter: &str = "AGATCGGAAGAGC";
+        let pool = build_pool(1).unwrap();
+        let mut records: Vec<Record> = get_records(&"fixtures/adapter.fq".to_string(), &ReadOptions::default()).unwrap();
+        let adapter_kmers: HashMap<String, u64> = count_kmers(&[adapter.to_string()], 5, &pool, &CountOptions::default()).unwrap();
+        let before: HashMap<String, u64> = count_kmers(&records.iter().map(|record| record.sequence.clone()).collect::<Vec<String>>(), 5, &pool, &CountOptions::default()).unwrap();
+        assert!(adapter_kmers.keys().any(|kmer| before.contains_key(kmer)));
+
+        for record in &mut records {
+            trim_record_adapter(record, adapter, 1);
+        }
+        let after: HashMap<String, u64> = count_kmers(&records.into_iter().map(|record| record.sequence).collect::<Vec<String>>(), 5, &pool, &CountOptions::default()).unwrap();
+        assert!(adapter_kmers.keys().all(|kmer| !after.contains_key(kmer)));
+    }
+
+    #[test]
+    fn adapter_n_matches_any_base() {
+        let read: &str = "ACGTACGTACGTAGTTCGGAAGAGC";
+        assert_eq!(trim_adapter(read, "AGATCGGAAGAGC", 0), read);
+        assert_eq!(trim_adapter(read, "AGNTCGGAAGAGC", 0), "ACGTACGTACGT");
+        assert_eq!(trim_adapter(read, "agntcggaagagc", 0), "ACGTACGTACGT");
+        // A literal N in the read is only matched by the wildcard
+        assert_eq!(trim_adapter("ACGTNCGG", "TACG", 0), "ACGTNCGG");
+        assert_eq!(trim_adapter("ACGTNCGG", "TNCG", 0), "ACG");
+    }
+
+    #[test]
+    fn adapter_symbols_are_checked() {
+        assert!(check_adapter("AGATCGGAAGAGC", None).is_ok());
+        assert!(check_adapter("agnnNCG", None).is_ok());
+        assert!(matches!(check_adapter("AGAUCG", None), Err(KmerError::Argument(_))));
+        let alphabet: Alphabet = Alphabet::parse("A U\nC G\n").unwrap();
+        assert!(check_adapter("AGAUCN", Some(&alphabet)).is_ok());
+        assert!(matches!(check_adapter("AGATCG", Some(&alphabet)), Err(KmerError::Argument(_))));
+    }
+
+
+    // fixtures/short.fa has 4 and 6 bases, at 5 only the second is counted
+    #[test]
+    fn short_sequences_are_filtered() {
+        let records: Vec<Record> = get_records(&"fixtures/short.fa".to_string(), &ReadOptions::default()).unwrap();
+        let (kept, dropped) = filter_min_length(records.clone(), 5);
+        assert_eq!(dropped, 1);
+        let sequences: Vec<String> = kept.into_iter().map(|record| record.sequence).collect();
+        assert_eq!(sequences, vec!["ACGTAC"]);
+        assert_eq!(counts(&sequences), HashMap::from([(String::from("ACG"), 1), (String::from("CGT"), 1), (String::from("GTA"), 1), (String::from("TAC"), 1)]));
+
+        let (kept, dropped) = filter_min_length(records, 4);
+        assert_eq!((kept.len(), dropped), (2, 0));
+    }
+
+
+    #[test]
+    fn ns_are_trimmed_from_the_ends_only() {
+        assert_eq!(trim_ns("NNACGTNN"), "ACGT");
+        assert_eq!(trim_ns("nNACNGTn"), "ACNGT");
+        assert_eq!(trim_ns("NNNN"), "");
+        assert_eq!(trim_ns("ACGT"), "ACGT");
+
+        let mut record: Record = Record { id: String::from("r"), sequence: String::from("NNACGTNN"), quality: Some(String::from("!!ABCD##")) };
+        trim_record_ns(&mut record);
+        assert_eq!((record.sequence.as_str(), record.quality.as_deref()), ("ACGT", Some("ABCD")));
+        let mut record: Record = Record { id: String::from("r"), sequence: String::from("NNNN"), quality: Some(String::from("IIII")) };
+        trim_record_ns(&mut record);
+        assert_eq!((record.sequence.as_str(), record.quality.as_deref()), ("", Some("")));
+    }
+
+    // Sequences are counted one by one, no window spans the end of one record and
+    // the start of the next
+    #[test]
+    fn no_kmer_spans_two_sequences() {
+        let sequences: Vec<String> = fixture_sequences("short.fa");
+        let separate: HashMap<String, u64> = counts(&sequences);
+        assert_eq!(separate, HashMap::from([(String::from("ACG"), 2), (String::from("CGT"), 2), (String::from("GTA"), 1), (String::from("TAC"), 1)]));
+        assert_ne!(separate, counts(&[sequences.concat()]));
+    }
+
+    // A circular sequence of n bases has n windows, k - 1 of them across the origin
+    #[test]
+    fn circular_sequences_wrap_around() {
+        assert_eq!(circularize("ACGTT", 3), "ACGTTAC");
+        let wrapped: HashMap<String, u64> = counts(&[circularize("ACGTT", 3)]);
+        assert_eq!(wrapped.values().sum::<u64>(), 5);
+        assert_eq!(wrapped["TTA"], 1);
+        assert_eq!(wrapped["TAC"], 1);
+
+        assert_eq!(circularize("AC", 3), "AC");
+        assert_eq!(circularize("ACGTT", 1), "ACGTT");
+    }
+
+    // Only b, the ten bases of TTTTTTTTGG, is counted
+    #[test]
+    fn only_the_longest_record_is_counted() {
+        let records: Vec<Record> = longest_record(get_records(&"fixtures/longest.fa".to_string(), &ReadOptions::default()).unwrap());
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, "b");
+        let sequences: Vec<String> = records.into_iter().map(|record| record.sequence).collect();
+        assert_eq!(counts(&sequences), HashMap::from([(String::from("TTT"), 6), (String::from("TTG"), 1), (String::from("TGG"), 1)]));
+
+        let record = |id: &str| Record { id: id.to_string(), sequence: String::from("ACGT"), quality: None };
+        assert_eq!(longest_record(vec![record("x"), record("y")])[0].id, "x");
+        assert!(longest_record(Vec::new()).is_empty());
+    }
+
+}
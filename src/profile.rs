@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::counter::Count;
+
+// Distinct k-mers and total occurrences that share a number of G/C bases
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GcBucket {
+    pub distinct_kmers: u64,
+    pub total_kmers: u64,
+}
+
+fn gc_content(kmer: &str) -> usize {
+    kmer.bytes().filter(|base| matches!(base, b'G' | b'C' | b'g' | b'c')).count()
+}
+
+// One bucket per possible G/C count 0..=k
+pub fn gc_profile<C: Count>(kmer_hashmap: &HashMap<String, C>, k: usize) -> Vec<GcBucket> {
+
+    let mut buckets: Vec<GcBucket> = vec![GcBucket::default(); k + 1];
+
+    for (key, &value) in kmer_hashmap {
+        let bucket: &mut GcBucket = &mut buckets[gc_content(key).min(k)];
+        bucket.distinct_kmers += 1;
+        bucket.total_kmers += value.to_u64();
+    }
+    buckets
+}
+
+pub fn save_gc_profile(buckets: &[GcBucket], path: &str) -> io::Result<()> {
+
+    let mut file = BufWriter::new(File::create(path)?);
+
+    writeln!(file, "gc\tdistinct_kmers\ttotal_kmers")?;
+    for (gc, bucket) in buckets.iter().enumerate() {
+        writeln!(file, "{}\t{}\t{}", gc, bucket.distinct_kmers, bucket.total_kmers)?;
+    }
+
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::output::load_counts;
+    use crate::testing::temp_path;
+
+    // ACG 3 and CGT 2 have two G/C bases, GTA 1 and TAC 1 one
+    #[test]
+    fn gc_buckets_match_hand_counts() {
+        let counts: HashMap<String, u64> = load_counts("fixtures/legacy.k3.tsv").unwrap();
+        let buckets: Vec<GcBucket> = gc_profile(&counts, 3);
+        let tallies: Vec<(u64, u64)> = buckets.iter().map(|bucket| (bucket.distinct_kmers, bucket.total_kmers)).collect();
+        assert_eq!(tallies, vec![(0, 0), (2, 2), (2, 5), (0, 0)]);
+
+        let path: String = temp_path("gc_profile.tsv");
+        save_gc_profile(&buckets, &path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "gc\tdistinct_kmers\ttotal_kmers\n0\t0\t0\n1\t2\t2\n2\t2\t5\n3\t0\t0\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn soft_masked_bases_count_as_gc() {
+        let counts: HashMap<String, u32> = HashMap::from([(String::from("gcAT"), 4), (String::from("GCGC"), 1)]);
+        let buckets: Vec<GcBucket> = gc_profile(&counts, 4);
+        assert_eq!(buckets[2], GcBucket { distinct_kmers: 1, total_kmers: 4 });
+        assert_eq!(buckets[4], GcBucket { distinct_kmers: 1, total_kmers: 1 });
+    }
+}
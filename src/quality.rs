@@ -0,0 +1,214 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::error::KmerError;
+use crate::sequences::Record;
+
+// Phred score encoding of FASTQ quality strings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PhredOffset {
+    Phred33,
+    Phred64,
+    // Decide from the range of quality characters in the input
+    Auto,
+}
+
+impl PhredOffset {
+
+    pub fn parse(value: &str) -> Result<PhredOffset, KmerError> {
+        match value {
+            "33" => Ok(PhredOffset::Phred33),
+            "64" => Ok(PhredOffset::Phred64),
+            "auto" => Ok(PhredOffset::Auto),
+            _ => Err(KmerError::Argument(String::from("--phred must be 33, 64 or auto"))),
+        }
+    }
+
+    // Resolves Auto against the given quality strings, other variants are returned as is
+    pub fn resolve<'a>(self, qualities: impl Iterator<Item = &'a str>) -> PhredOffset {
+        match self {
+            PhredOffset::Auto => detect_offset(qualities),
+            offset => offset,
+        }
+    }
+
+    pub fn value(self) -> u8 {
+        match self {
+            PhredOffset::Phred64 => 64,
+            _ => 33,
+        }
+    }
+}
+
+// Characters below ';' only occur in Phred+33, characters above 'J' only in Phred+64.
+// Input without either is ambiguous and treated as the modern Phred+33.
+pub fn detect_offset<'a>(qualities: impl Iterator<Item = &'a str>) -> PhredOffset {
+
+    let mut above_phred33_range: bool = false;
+
+    for quality in qualities {
+        for byte in quality.bytes() {
+            if byte < b';' {
+                return PhredOffset::Phred33;
+            }
+            if byte > b'J' {
+                above_phred33_range = true;
+            }
+        }
+    }
+    if above_phred33_range { PhredOffset::Phred64 } else { PhredOffset::Phred33 }
+}
+
+pub fn decode_quality(quality: &str, offset: PhredOffset) -> Vec<u8> {
+    quality.bytes().map(|byte| byte.saturating_sub(offset.value())).collect()
+}
+
+// BAM stores raw scores, its records carry them at offset 33 whatever --phred
+// says. The records of the other inputs are read at the offset (Auto resolved
+// over their qualities only) and re-encoded at 33, so after this all records
+// share offset 33. Returns the resolved offset of the non-BAM inputs.
+pub fn rebase_qualities(inputs: &mut [Vec<Record>], bam_inputs: &[bool], offset: PhredOffset) -> PhredOffset {
+
+    let offset: PhredOffset = offset.resolve(inputs.iter().zip(bam_inputs)
+        .filter(|&(_, &bam)| !bam)
+        .flat_map(|(records, _)| records.iter().filter_map(|record| record.quality.as_deref())));
+
+    if offset != PhredOffset::Phred33 {
+        for (records, _) in inputs.iter_mut().zip(bam_inputs).filter(|&(_, &bam)| !bam) {
+            for quality in records.iter_mut().filter_map(|record| record.quality.as_mut()) {
+                *quality = decode_quality(quality, offset).into_iter().map(|score| (score.min(93) + 33) as char).collect();
+            }
+        }
+    }
+    offset
+}
+
+// Range of the bases left after stripping the leading and trailing bases whose
+// Phred score is below min_quality
+fn quality_bounds(sequence: &str, quality: &str, offset: PhredOffset, min_quality: u8) -> (usize, usize) {
+
+    let scores: Vec<u8> = decode_quality(quality, offset);
+
+    let start: usize = scores.iter().position(|&score| score >= min_quality).unwrap_or(scores.len());
+    let end: usize = scores.iter().rposition(|&score| score >= min_quality).map_or(start, |last| last + 1);
+
+    // Guard against quality strings that are longer than the sequence
+    let end: usize = end.min(sequence.len());
+    (start.min(end), end)
+}
+
+// Strips leading and trailing bases whose Phred score is below min_quality
+pub fn trim_by_quality<'a>(sequence: &'a str, quality: &str, offset: PhredOffset, min_quality: u8) -> &'a str {
+    let (start, end) = quality_bounds(sequence, quality, offset, min_quality);
+    &sequence[start..end]
+}
+
+// trim_by_quality on a whole record, the quality string is trimmed along with the sequence
+pub fn trim_record_quality(record: &mut Record, offset: PhredOffset, min_quality: u8) {
+    let Some(quality) = &record.quality else {
+        return;
+    };
+    let (start, end) = quality_bounds(&record.sequence, quality, offset, min_quality);
+    record.quality = Some(quality[start.min(quality.len())..end.min(quality.len())].to_string());
+    record.sequence = record.sequence[start..end].to_string();
+}
+
+// Number of bases per Phred score, indexed by the score up to the highest one seen
+pub fn quality_histogram<'a>(qualities: impl Iterator<Item = &'a str>, offset: PhredOffset) -> Vec<u64> {
+
+    let mut histogram: Vec<u64> = Vec::new();
+
+    for quality in qualities {
+        for score in decode_quality(quality, offset) {
+            if histogram.len() <= score as usize {
+                histogram.resize(score as usize + 1, 0);
+            }
+            histogram[score as usize] += 1;
+        }
+    }
+    histogram
+}
+
+pub fn save_quality_histogram(histogram: &[u64], path: &str) -> io::Result<()> {
+
+    let mut file = BufWriter::new(File::create(path)?);
+
+    writeln!(file, "quality\tbases")?;
+    for (score, bases) in histogram.iter().enumerate() {
+        writeln!(file, "{}\t{}", score, bases)?;
+    }
+
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequences::{get_records, ReadOptions};
+
+    fn read(file: &str) -> Vec<Record> {
+        get_records(&file.to_string(), &ReadOptions::default()).unwrap()
+    }
+
+    // tiny.bam holds the reads of tiny.fq, Phred+64 FASTQ next to it must not
+    // shift the BAM scores
+    #[test]
+    fn bam_qualities_stay_at_offset_33() {
+        let phred33: Vec<Record> = read("fixtures/tiny.fq");
+        let phred64: Vec<Record> = phred33.iter().map(|record| Record {
+            id: record.id.clone(),
+            sequence: record.sequence.clone(),
+            quality: record.quality.as_ref().map(|quality| quality.bytes().map(|byte| (byte + 31) as char).collect()),
+        }).collect();
+        let bam: Vec<Record> = read("fixtures/tiny.bam");
+        assert_eq!(bam[0].quality, phred33[0].quality);
+
+        let mut inputs: Vec<Vec<Record>> = vec![phred64, bam.clone()];
+        assert_eq!(rebase_qualities(&mut inputs, &[false, true], PhredOffset::Auto), PhredOffset::Phred64);
+        assert_eq!(inputs[0], phred33);
+        assert_eq!(inputs[1], bam);
+
+        let mut inputs: Vec<Vec<Record>> = vec![bam.clone()];
+        assert_eq!(rebase_qualities(&mut inputs, &[true], PhredOffset::Phred64), PhredOffset::Phred64);
+        assert_eq!(inputs[0], bam);
+    }
+
+    // 'h' is Q40 at offset 64 but Q71 at 33, '@' Q0 at 64 but Q31 at 33
+    #[test]
+    fn offsets_decode_and_trim_differently() {
+        let quality: &str = "@@hhhh@@";
+        assert_eq!(decode_quality(quality, PhredOffset::Phred64), vec![0, 0, 40, 40, 40, 40, 0, 0]);
+        assert_eq!(decode_quality(quality, PhredOffset::Phred33), vec![31, 31, 71, 71, 71, 71, 31, 31]);
+        assert_eq!(trim_by_quality("ACGTACGT", quality, PhredOffset::Phred64, 20), "GTAC");
+        assert_eq!(trim_by_quality("ACGTACGT", quality, PhredOffset::Phred33, 20), "ACGTACGT");
+    }
+
+    #[test]
+    fn offset_is_detected_from_the_quality_range() {
+        assert_eq!(detect_offset(["@@hhhh@@"].into_iter()), PhredOffset::Phred64);
+        assert_eq!(detect_offset(["hhhh", "II#I"].into_iter()), PhredOffset::Phred33);
+        // Only characters both encodings share, the modern one is assumed
+        assert_eq!(detect_offset(["@ABCDEFGHIJ"].into_iter()), PhredOffset::Phred33);
+        assert_eq!(PhredOffset::Auto.resolve(["hhhh"].into_iter()), PhredOffset::Phred64);
+        assert_eq!(PhredOffset::Phred33.resolve(["hhhh"].into_iter()), PhredOffset::Phred33);
+        assert!(PhredOffset::parse("65").is_err());
+    }
+
+
+    // !!+5 and 55I: two bases at Q0, one at Q10, three at Q20 and one at Q40
+    #[test]
+    fn histogram_buckets_of_the_fixture() {
+        let records: Vec<Record> = read("fixtures/qual_hist.fq");
+        let histogram: Vec<u64> = quality_histogram(records.iter().filter_map(|record| record.quality.as_deref()), PhredOffset::Phred33);
+        assert_eq!(histogram.len(), 41);
+        assert_eq!((histogram[0], histogram[10], histogram[20], histogram[40]), (2, 1, 3, 1));
+        assert_eq!(histogram.iter().sum::<u64>(), 7);
+
+        let path: String = crate::testing::temp_path("qual_hist.tsv");
+        save_quality_histogram(&histogram, &path).unwrap();
+        let written: String = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!((lines.len(), lines[0], lines[1], lines[21], lines[41]), (42, "quality\tbases", "0\t2", "20\t3", "40\t1"));
+    }
+}
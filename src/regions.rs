@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use crate::sequences::Record;
+
+// 0-based, half-open intervals per chromosome, as in BED
+pub type Regions = HashMap<String, Vec<(usize, usize)>>;
+
+// Reads the chrom, start and end columns of a BED file. Header, track and
+// comment lines are skipped, overlapping intervals are merged so no base is
+// counted twice.
+pub fn load_bed(path: &str) -> io::Result<Regions> {
+
+    let reader = BufReader::new(File::open(path)?);
+    let mut regions: Regions = HashMap::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 3 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("BED line needs chrom, start and end: {}", line)));
+        }
+        let parse = |field: &str| field.trim().parse::<usize>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid BED coordinate in line: {}", line)));
+        let start: usize = parse(fields[1])?;
+        let end: usize = parse(fields[2])?;
+        if start < end {
+            regions.entry(fields[0].to_string()).or_default().push((start, end));
+        }
+    }
+
+    for intervals in regions.values_mut() {
+        intervals.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(intervals.len());
+        for &(start, end) in intervals.iter() {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        *intervals = merged;
+    }
+
+    Ok(regions)
+}
+
+// Replaces every record by one record per interval on it, matched on the first
+// word of the header. Intervals reaching past the sequence end are clamped,
+// records without intervals are dropped.
+pub fn select_regions(records: Vec<Record>, regions: &Regions) -> Vec<Record> {
+
+    let mut selected: Vec<Record> = Vec::new();
+
+    for record in records {
+        let name: &str = record.id.split_whitespace().next().unwrap_or("");
+        let Some(intervals) = regions.get(name) else {
+            continue;
+        };
+        for &(start, end) in intervals {
+            let end: usize = end.min(record.sequence.len());
+            if start >= end {
+                continue;
+            }
+            selected.push(Record {
+                id: format!("{}:{}-{}", name, start, end),
+                sequence: record.sequence[start..end].to_string(),
+                quality: record.quality.as_ref().map(|quality| quality[start..end.min(quality.len())].to_string()),
+            });
+        }
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::count::{build_pool, count_kmers, CountOptions};
+    use crate::sequences::{get_records, ReadOptions};
+    use crate::testing::temp_file;
+
+    // Overlapping intervals merge, the last one is clamped to the 16 bases of
+    // chr1 and chr2 is not in the FASTA
+    #[test]
+    fn only_the_selected_intervals_are_counted() {
+        let fasta: String = temp_file("regions.fa", b">chr1 assembled\nAAAACCCCGGGGTTTT\n>chr3\nACGTACGT\n");
+        let bed: String = temp_file("regions.bed", b"track name=test\nchr1\t4\t8\nchr1\t6\t10\tgene\nchr1\t14\t40\nchr2\t0\t5\n");
+
+        let regions: Regions = load_bed(&bed).unwrap();
+        assert_eq!(regions["chr1"], vec![(4, 10), (14, 40)]);
+        let records: Vec<Record> = select_regions(get_records(&fasta, &ReadOptions::default()).unwrap(), &regions);
+        let selected: Vec<(&str, &str)> = records.iter().map(|record| (record.id.as_str(), record.sequence.as_str())).collect();
+        assert_eq!(selected, vec![("chr1:4-10", "CCCCGG"), ("chr1:14-16", "TT")]);
+
+        let sequences: Vec<String> = records.into_iter().map(|record| record.sequence).collect();
+        let counts: HashMap<String, u32> = count_kmers(&sequences, 3, &build_pool(1).unwrap(), &CountOptions::default()).unwrap();
+        assert_eq!(counts, HashMap::from([(String::from("CCC"), 2), (String::from("CCG"), 1), (String::from("CGG"), 1)]));
+
+        std::fs::remove_file(fasta).unwrap();
+        std::fs::remove_file(bed).unwrap();
+    }
+
+    #[test]
+    fn bed_lines_need_three_columns() {
+        let bed: String = temp_file("short.bed", b"chr1\t4\n");
+        assert_eq!(load_bed(&bed).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(bed).unwrap();
+    }
+}
@@ -0,0 +1,153 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::Duration;
+
+// Machine-readable summary of a run, written with --report
+pub struct RunReport {
+    pub input_files: Vec<String>,
+    pub k: usize,
+    pub threads: usize,
+    pub sequences: usize,
+    pub bases: usize,
+    // Sequences removed by the length filter before counting
+    pub filtered_sequences: usize,
+    pub distinct_kmers: usize,
+    // Not tracked in presence-only (--set) mode
+    pub total_kmers: Option<u64>,
+    // Phase name and elapsed time, in the order the phases ran
+    pub timings: Vec<(String, Duration)>,
+    // Filter name and threshold, only for filters that were applied
+    pub filters: Vec<(String, f64)>,
+    // Counting was stopped early, the counts only cover part of the input
+    pub partial: bool,
+    // Why a partial run stopped, "interrupted" or "time limit"
+    pub partial_reason: Option<String>,
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut escaped: String = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+impl RunReport {
+
+    pub fn to_json(&self) -> String {
+
+        let input_files: Vec<String> = self.input_files.iter().map(|f| json_string(f)).collect();
+        let timings: Vec<String> = self.timings.iter()
+            .map(|(phase, elapsed)| format!("{}: {}", json_string(phase), elapsed.as_secs_f64()))
+            .collect();
+        let filters: Vec<String> = self.filters.iter()
+            .map(|(name, threshold)| format!("{}: {}", json_string(name), threshold))
+            .collect();
+
+        let mut json: String = String::from("{\n");
+        json.push_str(&format!("  \"input_files\": [{}],\n", input_files.join(", ")));
+        json.push_str(&format!("  \"k\": {},\n", self.k));
+        json.push_str(&format!("  \"threads\": {},\n", self.threads));
+        json.push_str(&format!("  \"sequences\": {},\n", self.sequences));
+        json.push_str(&format!("  \"bases\": {},\n", self.bases));
+        json.push_str(&format!("  \"filtered_sequences\": {},\n", self.filtered_sequences));
+        json.push_str(&format!("  \"distinct_kmers\": {},\n", self.distinct_kmers));
+        match self.total_kmers {
+            Some(total_kmers) => json.push_str(&format!("  \"total_kmers\": {},\n", total_kmers)),
+            None => json.push_str("  \"total_kmers\": null,\n"),
+        }
+        json.push_str(&format!("  \"timings_seconds\": {{{}}},\n", timings.join(", ")));
+        json.push_str(&format!("  \"filters\": {{{}}},\n", filters.join(", ")));
+        json.push_str(&format!("  \"partial\": {},\n", self.partial));
+        match &self.partial_reason {
+            Some(reason) => json.push_str(&format!("  \"partial_reason\": {}\n", json_string(reason))),
+            None => json.push_str("  \"partial_reason\": null\n"),
+        }
+        json.push_str("}\n");
+        json
+    }
+
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(self.to_json().as_bytes())?;
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+
+    use super::*;
+    use crate::count::{build_pool, count_kmers, CountOptions};
+    use crate::sequences::{get_records, ReadOptions};
+    use crate::testing::{temp_path, Json};
+
+    // The report of a small run of fixtures/tiny.fq, as main builds it
+    fn small_run() -> RunReport {
+        let file: String = String::from("fixtures/tiny.fq");
+        let sequences: Vec<String> = get_records(&file, &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect();
+        let counts: HashMap<String, u32> = count_kmers(&sequences, 5, &build_pool(2).unwrap(), &CountOptions::default()).unwrap();
+        RunReport {
+            input_files: vec![file, String::from("odd \"name\"\t.fa")],
+            k: 5,
+            threads: 2,
+            sequences: sequences.len(),
+            bases: sequences.iter().map(String::len).sum(),
+            filtered_sequences: 0,
+            distinct_kmers: counts.len(),
+            total_kmers: Some(counts.values().map(|&count| count as u64).sum()),
+            timings: vec![(String::from("read"), Duration::from_millis(3)), (String::from("count"), Duration::from_micros(1500))],
+            filters: vec![(String::from("trim_quality"), 20.0)],
+            partial: false,
+            partial_reason: None,
+        }
+    }
+
+    #[test]
+    fn report_schema() {
+        let report: RunReport = small_run();
+        let path: String = temp_path("report.json");
+        report.write(&path).unwrap();
+        let json: Json = Json::parse(&fs::read_to_string(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(json.keys(), vec![
+            "input_files", "k", "threads", "sequences", "bases", "filtered_sequences", "distinct_kmers",
+            "total_kmers", "timings_seconds", "filters", "partial", "partial_reason",
+        ]);
+        assert_eq!(json.get("input_files"), &Json::Array(vec![Json::String(String::from("fixtures/tiny.fq")), Json::String(String::from("odd \"name\"\t.fa"))]));
+        for key in ["k", "threads", "sequences", "bases", "filtered_sequences", "distinct_kmers", "total_kmers"] {
+            assert!(matches!(json.get(key), Json::Number(value) if *value >= 0.0 && value.fract() == 0.0), "{} is not a count", key);
+        }
+        assert_eq!(json.get("k"), &Json::Number(5.0));
+        assert_eq!(json.get("sequences"), &Json::Number(40.0));
+        assert_eq!(json.get("distinct_kmers"), &Json::Number(report.distinct_kmers as f64));
+        assert_eq!(json.get("timings_seconds"), &Json::Object(vec![(String::from("read"), Json::Number(0.003)), (String::from("count"), Json::Number(0.0015))]));
+        assert_eq!(json.get("filters"), &Json::Object(vec![(String::from("trim_quality"), Json::Number(20.0))]));
+        assert_eq!(json.get("partial"), &Json::Bool(false));
+        assert_eq!(json.get("partial_reason"), &Json::Null);
+    }
+
+    // Presence-only runs have no total, stopped runs say why
+    #[test]
+    fn report_nulls_and_partial_runs() {
+        let report: RunReport = RunReport { total_kmers: None, partial: true, partial_reason: Some(String::from("time limit")), filters: Vec::new(), ..small_run() };
+        let json: Json = Json::parse(&report.to_json());
+        assert_eq!(json.get("total_kmers"), &Json::Null);
+        assert_eq!(json.get("filters"), &Json::Object(Vec::new()));
+        assert_eq!(json.get("partial"), &Json::Bool(true));
+        assert_eq!(json.get("partial_reason"), &Json::String(String::from("time limit")));
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeSet;
+use rayon::{ThreadPool, prelude::*};
+
+use crate::count::{split_into_tasks, task_keys, CountOptions};
+
+pub(crate) fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// Seeded pseudo-random priority of a k-mer, a pure function of (k-mer, seed)
+fn priority(kmer: &str, seed: u64) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in kmer.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    splitmix64(hash ^ splitmix64(seed))
+}
+
+// Bounded reservoir holding the n lowest-priority k-mers seen so far
+struct Reservoir {
+    size: usize,
+    entries: BTreeSet<(u64, String)>,
+}
+
+impl Reservoir {
+
+    fn new(size: usize) -> Reservoir {
+        Reservoir { size, entries: BTreeSet::new() }
+    }
+
+    fn offer(&mut self, kmer: &str, seed: u64) {
+        if self.size == 0 {
+            return;
+        }
+        let rank: u64 = priority(kmer, seed);
+        if self.entries.len() == self.size && self.entries.last().is_some_and(|last| rank > last.0) {
+            return;
+        }
+        self.entries.insert((rank, kmer.to_string()));
+        if self.entries.len() > self.size {
+            self.entries.pop_last();
+        }
+    }
+
+    fn merge(mut self, other: Reservoir) -> Reservoir {
+        for (rank, kmer) in other.entries {
+            self.entries.insert((rank, kmer));
+            if self.entries.len() > self.size {
+                self.entries.pop_last();
+            }
+        }
+        self
+    }
+}
+
+// Uniform sample of `size` distinct k-mers. Every thread keeps a reservoir of
+// the k-mers with the lowest seeded priorities; a k-mer's priority does not
+// depend on how often or where it occurs, so repeats cannot bias the sample.
+// Reservoirs are merged by keeping the `size` lowest priorities of their union,
+// which is exactly the reservoir a single thread would have built. The sample
+// therefore only depends on the input and the seed, not on the thread count.
+pub fn reservoir_sample(sequences: &[String], k: usize, size: usize, seed: u64, pool: &ThreadPool, options: &CountOptions) -> Vec<String> {
+
+    let tasks: Vec<(bool, &str)> = split_into_tasks(sequences, k, options);
+
+    let reservoir: Reservoir = pool.install(|| {
+        tasks.par_iter()
+            .fold(|| Reservoir::new(size), |mut reservoir, &(_, sequence)| {
+                for key in task_keys(sequence, k, options) {
+                    reservoir.offer(&key, seed);
+                }
+                reservoir
+            })
+            .reduce(|| Reservoir::new(size), Reservoir::merge)
+    });
+
+    let mut sample: Vec<String> = reservoir.entries.into_iter().map(|(_, kmer)| kmer).collect();
+    sample.sort_unstable();
+    sample
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::*;
+    use crate::count::{build_pool, count_kmers};
+    use crate::sequences::{get_records, ReadOptions};
+
+    #[test]
+    fn reservoir_is_reproducible_and_distinct() {
+        let sequences: Vec<String> = get_records(&"fixtures/tiny.fq".to_string(), &ReadOptions::default()).unwrap().into_iter().map(|record| record.sequence).collect();
+        let options: CountOptions = CountOptions::default();
+        let counts: HashMap<String, u32> = count_kmers(&sequences, 5, &build_pool(1).unwrap(), &options).unwrap();
+
+        let sample: Vec<String> = reservoir_sample(&sequences, 5, 20, 42, &build_pool(1).unwrap(), &options);
+        assert_eq!(sample.len(), 20);
+        assert_eq!(sample.iter().collect::<HashSet<&String>>().len(), 20);
+        assert!(sample.iter().all(|kmer| counts.contains_key(kmer)));
+        assert!(sample.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // Same seed, any thread count: the same sample. Another seed differs.
+        for threads in [1, 2, 7] {
+            assert_eq!(reservoir_sample(&sequences, 5, 20, 42, &build_pool(threads).unwrap(), &options), sample);
+        }
+        assert_ne!(reservoir_sample(&sequences, 5, 20, 43, &build_pool(1).unwrap(), &options), sample);
+
+        // Asking for more than there are gives all distinct k-mers
+        let all: Vec<String> = reservoir_sample(&sequences, 5, counts.len() + 10, 42, &build_pool(2).unwrap(), &options);
+        assert_eq!(all.len(), counts.len());
+        assert!(reservoir_sample(&sequences, 5, 0, 42, &build_pool(2).unwrap(), &options).is_empty());
+    }
+}
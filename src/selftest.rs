@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use rayon::ThreadPool;
+
+use crate::count::{count_kmer_set, count_kmers, CountOptions};
+use crate::error::KmerError;
+use crate::sample::splitmix64;
+
+// Random A/C/G/T sequences with lengths between length / 2 and length, the same
+// for the same seed
+pub fn random_sequences(count: usize, length: usize, seed: u64) -> Vec<String> {
+
+    let mut state: u64 = splitmix64(seed);
+    let mut next = || {
+        state = splitmix64(state);
+        state
+    };
+
+    (0..count).map(|_| {
+        let length: usize = length / 2 + (next() % (length - length / 2 + 1) as u64) as usize;
+        let mut sequence: String = String::with_capacity(length);
+        while sequence.len() < length {
+            // 32 bases per random number
+            let mut bits: u64 = next();
+            for _ in 0..32.min(length - sequence.len()) {
+                sequence.push(b"ACGT"[(bits & 3) as usize] as char);
+                bits >>= 2;
+            }
+        }
+        sequence
+    }).collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct SelftestReport {
+    pub sequences: usize,
+    pub bases: usize,
+    pub distinct_kmers: usize,
+    pub total_kmers: u64,
+    pub elapsed: Duration,
+}
+
+impl SelftestReport {
+    pub fn megabases_per_second(&self) -> f64 {
+        self.bases as f64 / 1e6 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+}
+
+// Counts synthetic sequences and checks invariants that hold for any input:
+// every window is counted once (the total equals the sum of len - k + 1), the
+// presence-only set has as many k-mers as the count table, and canonical counting
+// keeps the total.
+pub fn selftest(count: usize, length: usize, k: usize, seed: u64, pool: &ThreadPool) -> Result<SelftestReport, KmerError> {
+
+    let sequences: Vec<String> = random_sequences(count, length, seed);
+    let bases: usize = sequences.iter().map(String::len).sum();
+    let expected: u64 = sequences.iter().map(|sequence| (sequence.len() + 1).saturating_sub(k) as u64).sum();
+    let options = CountOptions::default();
+
+    let start = Instant::now();
+    let kmer_hashmap: HashMap<String, u64> = count_kmers(&sequences, k, pool, &options)?;
+    let elapsed: Duration = start.elapsed();
+
+    let total_kmers: u64 = kmer_hashmap.values().sum();
+    if total_kmers != expected {
+        return Err(KmerError::Selftest(format!("counted {} k-mers, the sequences have {} windows", total_kmers, expected)));
+    }
+
+    let kmer_set_len: usize = count_kmer_set(&sequences, k, pool, &options).len();
+    if kmer_set_len != kmer_hashmap.len() {
+        return Err(KmerError::Selftest(format!("the k-mer set has {} k-mers, the count table {}", kmer_set_len, kmer_hashmap.len())));
+    }
+
+    let canonical = CountOptions { canonical: true, ..CountOptions::default() };
+    let canonical_total: u64 = count_kmers::<u64>(&sequences, k, pool, &canonical)?.values().sum();
+    if canonical_total != expected {
+        return Err(KmerError::Selftest(format!("counted {} canonical k-mers, the sequences have {} windows", canonical_total, expected)));
+    }
+
+    Ok(SelftestReport { sequences: sequences.len(), bases, distinct_kmers: kmer_hashmap.len(), total_kmers, elapsed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::count::build_pool;
+
+    #[test]
+    fn tiny_selftest_holds_its_invariants() {
+        let report: SelftestReport = selftest(50, 200, 11, 42, &build_pool(2).unwrap()).unwrap();
+        let sequences: Vec<String> = random_sequences(50, 200, 42);
+        assert_eq!(report.sequences, 50);
+        assert_eq!(report.bases, sequences.iter().map(String::len).sum::<usize>());
+        assert_eq!(report.total_kmers, sequences.iter().map(|sequence| (sequence.len() - 10) as u64).sum::<u64>());
+        assert!(report.distinct_kmers as u64 <= report.total_kmers);
+        assert!(report.megabases_per_second() > 0.0);
+    }
+
+    #[test]
+    fn random_sequences_depend_on_the_seed_only() {
+        let sequences: Vec<String> = random_sequences(20, 64, 1);
+        assert_eq!(sequences, random_sequences(20, 64, 1));
+        assert_ne!(sequences, random_sequences(20, 64, 2));
+        assert!(sequences.iter().all(|sequence| (32..=64).contains(&sequence.len()) && sequence.bytes().all(|base| b"ACGT".contains(&base))));
+    }
+}
@@ -0,0 +1,780 @@
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::vec;
+use std::path::Path;
+use std::rc::Rc;
+
+use rayon::{ThreadPool, prelude::*};
+
+use crate::bam::bam_records;
+use crate::error::KmerError;
+use crate::inflate::{gunzip, is_gzip};
+use crate::{debug, warn};
+
+// Input name that reads from standard input
+pub const STDIN: &str = "-";
+
+// Extensions picked up when expanding a directory into its sequence files
+const SEQUENCE_EXTENSIONS: [&str; 9] = ["fa", "fasta", "fna", "ffn", "frn", "faa", "fq", "fastq", "bam"];
+
+// One FASTA/FASTQ entry, quality is only present for FASTQ
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub id: String,
+    pub sequence: String,
+    pub quality: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Fasta,
+    Fastq,
+    // One sequence per line without headers, only selected explicitly
+    Raw,
+    // Reads of a BAM alignment file
+    Bam,
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Result<Format, KmerError> {
+        match value {
+            "fasta" => Ok(Format::Fasta),
+            "fastq" => Ok(Format::Fastq),
+            "raw" => Ok(Format::Raw),
+            "bam" => Ok(Format::Bam),
+            _ => Err(KmerError::Argument(String::from("--format must be fasta, fastq, raw or bam"))),
+        }
+    }
+
+    // Files ending in a (.fa, .fasta, ...) are FASTA, files ending in q (.fq, .fastq) FASTQ,
+    // .bam files BAM. A .gz suffix is looked through (reads.fq.gz is FASTQ).
+    pub fn from_path(file: &str) -> Option<Format> {
+        let file: &str = if file.to_ascii_lowercase().ends_with(".gz") { &file[..file.len() - 3] } else { file };
+        if file.to_ascii_lowercase().ends_with(".bam") {
+            return Some(Format::Bam);
+        }
+        if file.ends_with('a') {
+            return Some(Format::Fasta);
+        }
+        if file.ends_with('q') {
+            return Some(Format::Fastq);
+        }
+        None
+    }
+
+    // Sniffs the first non-empty line: '>' starts FASTA, '@' starts FASTQ
+    pub fn from_content<R: BufRead>(reader: &mut R) -> Result<Option<Format>, KmerError> {
+        let mut line: String = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            match line.trim_start().chars().next() {
+                Some('>') => return Ok(Some(Format::Fasta)),
+                Some('@') => return Ok(Some(Format::Fastq)),
+                Some(_) => return Ok(None),
+                None => line.clear(),
+            }
+        }
+        Ok(None)
+    }
+
+    // Same as from_content, on already buffered bytes that must not be consumed (stdin)
+    fn from_prefix(bytes: &[u8]) -> Option<Format> {
+        match bytes.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'>') => Some(Format::Fasta),
+            Some(b'@') => Some(Format::Fastq),
+            _ => None,
+        }
+    }
+}
+
+// Read buffer used unless configured otherwise, the same as BufReader's default
+pub const DEFAULT_READ_BUFFER: usize = 8 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct ReadOptions {
+    // Fail instead of warning when a file's content contradicts its extension
+    pub strict: bool,
+    // Capacity in bytes of the buffered reader used for parsing
+    pub buffer: usize,
+    // Format of every input, skipping detection
+    pub format: Option<Format>,
+    // Leave out secondary and supplementary alignments of BAM input
+    pub skip_secondary: bool,
+}
+
+impl Default for ReadOptions {
+    fn default() -> ReadOptions {
+        ReadOptions { strict: false, buffer: DEFAULT_READ_BUFFER, format: None, skip_secondary: false }
+    }
+}
+
+// BAM is recognized by --format or the .bam extension, its content is not sniffed
+pub fn is_bam(file: &str, options: &ReadOptions) -> bool {
+    options.format.or_else(|| Format::from_path(file)) == Some(Format::Bam)
+}
+
+// An explicit format wins, then content over the extension. A disagreement between
+// the latter two is an error under strict mode and a warning otherwise.
+
+pub fn resolve_format(file: &str, options: &ReadOptions) -> Result<Format, KmerError> {
+
+    if let Some(format) = options.format {
+        return Ok(format);
+    }
+
+    // Binary, there is no text content to sniff
+    if Format::from_path(file) == Some(Format::Bam) {
+        return Ok(Format::Bam);
+    }
+
+    let mut reader = BufReader::new(File::open(file)?);
+    if is_gzip(reader.fill_buf()?) {
+        let mut compressed: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut compressed)?;
+        return detect_format(file, &mut &gunzip(&compressed)?[..], options);
+    }
+    detect_format(file, &mut reader, options)
+}
+
+// resolve_format on the (decompressed) content of a file
+fn detect_format<R: BufRead>(file: &str, reader: &mut R, options: &ReadOptions) -> Result<Format, KmerError> {
+
+    if let Some(format) = options.format {
+        return Ok(format);
+    }
+
+    let content: Option<Format> = Format::from_content(reader)?;
+    let extension: Option<Format> = Format::from_path(file);
+
+    match (extension, content) {
+        (Some(extension), Some(content)) if extension != content => {
+            let mismatch = KmerError::FormatMismatch { file: file.to_string(), extension, content };
+            if options.strict {
+                return Err(mismatch);
+            }
+            warn!("Warning: {}, reading it as {}", mismatch, content);
+            Ok(content)
+        }
+        (_, Some(content)) => Ok(content),
+        (Some(extension), None) => Ok(extension),
+        (None, None) => Err(KmerError::UnknownFormat(file.to_string())),
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Fasta => write!(f, "FASTA"),
+            Format::Fastq => write!(f, "FASTQ"),
+            Format::Raw => write!(f, "raw"),
+            Format::Bam => write!(f, "BAM"),
+        }
+    }
+}
+
+// Lazily parses one record at a time, see records()
+pub struct Records<R: BufRead> {
+    lines: std::io::Lines<R>,
+    format: Format,
+    line_number: usize,
+    // FASTA header that ended the previous record
+    pending_header: Option<String>,
+    // BAM records, decoded as a whole up front
+    decoded: Option<vec::IntoIter<Result<Record, KmerError>>>,
+}
+
+impl<R: BufRead> Records<R> {
+
+    fn next_line(&mut self) -> Option<Result<String, KmerError>> {
+        let line = self.lines.next()?;
+        self.line_number += 1;
+        Some(line.map(|line| line.trim_end_matches('\r').to_string()).map_err(KmerError::from))
+    }
+
+    fn parse_error(&self, message: &str) -> KmerError {
+        KmerError::Parse { line: self.line_number, message: message.to_string() }
+    }
+
+    // Sequence and quality lines are ASCII, which keeps every byte offset used
+    // for windows, trimming and regions on a character boundary
+    fn ascii_line(&mut self) -> Option<Result<String, KmerError>> {
+        match self.next_line()? {
+            Ok(line) if !line.is_ascii() => Some(Err(self.parse_error("Non-ASCII character in sequence or quality line"))),
+            line => Some(line),
+        }
+    }
+
+    // Multi-line FASTA, records without sequence are skipped
+    fn next_fasta(&mut self) -> Option<Result<Record, KmerError>> {
+        loop {
+            let mut id: Option<String> = self.pending_header.take();
+            let mut sequence: String = String::new();
+
+            while let Some(line) = self.next_line() {
+                let line: String = match line {
+                    Ok(line) => line,
+                    Err(error) => return Some(Err(error)),
+                };
+                if let Some(header) = line.strip_prefix('>') {
+                    if id.is_none() && sequence.is_empty() {
+                        id = Some(header.to_string());
+                        continue;
+                    }
+                    self.pending_header = Some(header.to_string());
+                    break;
+                }
+                if !line.is_ascii() {
+                    return Some(Err(self.parse_error("Non-ASCII character in sequence or quality line")));
+                }
+                sequence.push_str(&line);
+            }
+
+            if !sequence.is_empty() {
+                return Some(Ok(Record { id: id.unwrap_or_default(), sequence, quality: None }));
+            }
+            self.pending_header.as_ref()?;
+        }
+    }
+
+    // Four lines per record: @header, sequence, +, quality
+    fn next_fastq(&mut self) -> Option<Result<Record, KmerError>> {
+
+        // Blank lines between records are tolerated
+        let header: String = loop {
+            match self.next_line()? {
+                Ok(line) if line.is_empty() => continue,
+                Ok(line) => break line,
+                Err(error) => return Some(Err(error)),
+            }
+        };
+        let Some(id) = header.strip_prefix('@') else {
+            return Some(Err(self.parse_error("FASTQ record must start with @")));
+        };
+        let id: String = id.to_string();
+
+        let mut fields: Vec<String> = Vec::with_capacity(3);
+        for _ in 0..3 {
+            match self.ascii_line() {
+                Some(Ok(line)) => fields.push(line),
+                Some(Err(error)) => return Some(Err(error)),
+                None => return Some(Err(self.parse_error("Truncated FASTQ record"))),
+            }
+        }
+        if !fields[1].starts_with('+') {
+            return Some(Err(self.parse_error("FASTQ separator line must start with +")));
+        }
+        if fields[0].len() != fields[2].len() {
+            return Some(Err(self.parse_error("FASTQ quality line must be as long as the sequence")));
+        }
+        let quality: String = fields.pop().unwrap();
+        let sequence: String = fields.swap_remove(0);
+
+        Some(Ok(Record { id, sequence, quality: Some(quality) }))
+    }
+}
+
+impl<R: BufRead> Records<R> {
+
+    // Every non-empty line is a sequence, identified by its line number
+    fn next_raw(&mut self) -> Option<Result<Record, KmerError>> {
+        loop {
+            match self.ascii_line()? {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => return Some(Ok(Record { id: self.line_number.to_string(), sequence: line, quality: None })),
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Records<R> {
+    type Item = Result<Record, KmerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.format {
+            Format::Fasta => self.next_fasta(),
+            Format::Fastq => self.next_fastq(),
+            Format::Raw => self.next_raw(),
+            Format::Bam => self.decoded.as_mut()?.next(),
+        }
+    }
+}
+
+fn read_records<R: BufRead>(mut reader: R, format: Format, skip_secondary: bool) -> Records<R> {
+
+    let mut decoded: Option<vec::IntoIter<Result<Record, KmerError>>> = None;
+    if format == Format::Bam {
+        let mut data: Vec<u8> = Vec::new();
+        let results: Vec<Result<Record, KmerError>> = match reader.read_to_end(&mut data).map_err(KmerError::from).and_then(|_| bam_records(&data, skip_secondary)) {
+            Ok(records) => records.into_iter().map(Ok).collect(),
+            Err(error) => vec![Err(error)],
+        };
+        decoded = Some(results.into_iter());
+    }
+
+    Records { lines: reader.lines(), format, line_number: 0, pending_header: None, decoded }
+}
+
+// Streaming parser, memory use is bounded by the largest record (BAM input is
+// decompressed as a whole)
+pub fn records<R: BufRead>(reader: R, format: Format) -> impl Iterator<Item = Result<Record, KmerError>> {
+    read_records(reader, format, false)
+}
+
+// Duplicate ids (the header up to the first whitespace) usually come from
+// concatenating the same file twice and break the features keyed on ids.
+// Only checked for FASTA, FASTQ/BAM reads legitimately share names (mates).
+// Returns the number of repeated ids that were warned about.
+fn check_duplicate_ids(records: &[Record], name: &str, strict: bool) -> Result<usize, KmerError> {
+
+    let mut seen: HashSet<&str> = HashSet::with_capacity(records.len());
+    let mut duplicates: Vec<&str> = Vec::new();
+    for record in records {
+        let id: &str = record.id.split_whitespace().next().unwrap_or("");
+        if !seen.insert(id) {
+            duplicates.push(id);
+        }
+    }
+
+    if let Some(&first) = duplicates.first() {
+        if strict {
+            return Err(KmerError::DuplicateId(first.to_string()));
+        }
+        warn!("Warning: {} repeats FASTA headers ({} duplicates), the first is >{}", name, duplicates.len(), first);
+    }
+    Ok(duplicates.len())
+}
+
+// Standard input can only be sniffed from what is already buffered
+fn stdin_records(options: &ReadOptions) -> Result<Vec<Record>, KmerError> {
+
+    let mut reader = BufReader::with_capacity(options.buffer, io::stdin().lock());
+    if options.format != Some(Format::Bam) && is_gzip(reader.fill_buf()?) {
+        let mut compressed: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut compressed)?;
+        let data: Vec<u8> = gunzip(&compressed)?;
+        let format: Format = options.format.or_else(|| Format::from_prefix(&data)).ok_or_else(|| KmerError::UnknownFormat(STDIN.to_string()))?;
+        return checked_records(&data[..], format, STDIN, options);
+    }
+    let format: Format = match options.format {
+        Some(format) => format,
+        None => Format::from_prefix(reader.fill_buf()?).ok_or_else(|| KmerError::UnknownFormat(STDIN.to_string()))?,
+    };
+
+    checked_records(reader, format, STDIN, options)
+}
+
+// Parses all records and checks the FASTA ids
+fn checked_records<R: BufRead>(reader: R, format: Format, file: &str, options: &ReadOptions) -> Result<Vec<Record>, KmerError> {
+    let records: Vec<Record> = read_records(reader, format, options.skip_secondary).collect::<Result<Vec<Record>, KmerError>>()?;
+    if format == Format::Fasta {
+        let name: &str = if file == STDIN { "standard input" } else { file };
+        check_duplicate_ids(&records, name, options.strict)?;
+    }
+    Ok(records)
+}
+
+// Gzip-compressed FASTA/FASTQ (recognized by its magic bytes, not the name) is
+// decompressed as a whole, then parsed like a plain file. BAM is always BGZF
+// compressed and decompressed by its own reader.
+fn file_records(file: &str, options: &ReadOptions) -> Result<Vec<Record>, KmerError> {
+
+    let mut reader = BufReader::with_capacity(options.buffer, File::open(file)?);
+    let bam: bool = is_bam(file, options);
+    if !bam && is_gzip(reader.fill_buf()?) {
+        let mut compressed: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut compressed)?;
+        let data: Vec<u8> = gunzip(&compressed)?;
+        let format: Format = detect_format(file, &mut &data[..], options)?;
+        debug!("Reading {} as gzip-compressed {}", file, format);
+        return checked_records(&data[..], format, file, options);
+    }
+
+    let format: Format = resolve_format(file, options)?;
+    debug!("Reading {} as {}", file, format);
+    checked_records(reader, format, file, options)
+}
+
+// All records of a file (or of standard input for "-"), errors name the input
+pub fn get_records(file: &String, options: &ReadOptions) -> Result<Vec<Record>, KmerError> {
+
+    let records: Result<Vec<Record>, KmerError> = if file == STDIN {
+        stdin_records(options)
+    } else {
+        file_records(file, options)
+    };
+
+    let name: &str = if file == STDIN { "standard input" } else { file };
+    records.map_err(|error| KmerError::Input { file: name.to_string(), error: Box::new(error) })
+}
+
+// get_records of several files, read (and decompressed) concurrently on the pool,
+// one file per task. The result is in input order, so it equals reading the
+// files one after the other; of several failing files the first one is reported.
+pub fn get_records_parallel(files: &[String], options: &ReadOptions, pool: &ThreadPool) -> Result<Vec<Vec<Record>>, KmerError> {
+    let results: Vec<Result<Vec<Record>, KmerError>> = pool.install(|| {
+        files.par_iter().map(|file| get_records(file, options)).collect()
+    });
+    results.into_iter().collect()
+}
+
+// Tallies the bytes the parser consumed (not what was buffered ahead), the
+// parsed share of a sampled file
+struct CountingReader<R: BufRead> {
+    inner: R,
+    bytes: Rc<Cell<u64>>,
+}
+
+impl<R: BufRead> Read for CountingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read: usize = self.inner.read(buffer)?;
+        self.bytes.set(self.bytes.get() + read as u64);
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.bytes.set(self.bytes.get() + amount as u64);
+        self.inner.consume(amount);
+    }
+}
+
+// Records until max_bases bases are reached (the record crossing the limit is
+// kept), and whether that was all of them
+fn take_bases(records: impl Iterator<Item = Result<Record, KmerError>>, max_bases: usize) -> Result<(Vec<Record>, bool), KmerError> {
+    let mut taken: Vec<Record> = Vec::new();
+    let mut bases: usize = 0;
+    for record in records {
+        if bases >= max_bases {
+            return Ok((taken, false));
+        }
+        let record: Record = record?;
+        bases += record.sequence.len();
+        taken.push(record);
+    }
+    Ok((taken, true))
+}
+
+// A prefix of a file's records, see sample_records, and the fraction of the
+// file it covers: parsed bytes over all (decompressed) bytes, for BAM bases
+// over all bases. Compressed and BAM files are decompressed as a whole, only
+// the parsing stops early.
+fn sample_file(file: &str, options: &ReadOptions, max_bases: usize) -> Result<(Vec<Record>, f64), KmerError> {
+
+    let mut reader = BufReader::with_capacity(options.buffer, File::open(file)?);
+    let parsed: Rc<Cell<u64>> = Rc::new(Cell::new(0));
+
+    if is_bam(file, options) {
+        let mut data: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let records: Vec<Record> = bam_records(&data, options.skip_secondary)?;
+        let total: usize = records.iter().map(|record| record.sequence.len()).sum();
+        let (taken, _) = take_bases(records.into_iter().map(Ok), max_bases)?;
+        let sampled: usize = taken.iter().map(|record| record.sequence.len()).sum();
+        return Ok((taken, if total == 0 { 1.0 } else { sampled as f64 / total as f64 }));
+    }
+
+    if is_gzip(reader.fill_buf()?) {
+        let mut compressed: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut compressed)?;
+        let data: Vec<u8> = gunzip(&compressed)?;
+        let format: Format = detect_format(file, &mut &data[..], options)?;
+        let counting = CountingReader { inner: &data[..], bytes: Rc::clone(&parsed) };
+        let (taken, complete) = take_bases(read_records(counting, format, options.skip_secondary), max_bases)?;
+        return Ok((taken, if complete || data.is_empty() { 1.0 } else { parsed.get() as f64 / data.len() as f64 }));
+    }
+
+    let format: Format = resolve_format(file, options)?;
+    let size: u64 = fs::metadata(file)?.len();
+    let counting = CountingReader { inner: reader, bytes: Rc::clone(&parsed) };
+    let (taken, complete) = take_bases(read_records(counting, format, options.skip_secondary), max_bases)?;
+    Ok((taken, if complete || size == 0 { 1.0 } else { (parsed.get() as f64 / size as f64).min(1.0) }))
+}
+
+// The first records of the inputs, in order, up to about max_bases bases in
+// total, for --estimate-memory. Returns the records per input (inputs past the
+// limit are not opened) and the fraction of the input they cover, weighted by
+// file size, to extrapolate from. With standard input among the inputs
+// everything is read, its size is unknown.
+pub fn sample_records(files: &[String], options: &ReadOptions, max_bases: usize) -> Result<(Vec<Vec<Record>>, f64), KmerError> {
+
+    if files.iter().any(|file| file == STDIN) {
+        let records: Vec<Vec<Record>> = files.iter().map(|file| get_records(file, options)).collect::<Result<_, _>>()?;
+        return Ok((records, 1.0));
+    }
+
+    let input_error = |file: &String, error: KmerError| KmerError::Input { file: file.clone(), error: Box::new(error) };
+    let sizes: Vec<u64> = files.iter().map(|file| fs::metadata(file).map(|metadata| metadata.len()).map_err(|error| input_error(file, KmerError::Io(error)))).collect::<Result<_, _>>()?;
+    let total: u64 = sizes.iter().sum();
+
+    let mut records: Vec<Vec<Record>> = Vec::new();
+    let mut covered: f64 = 0.0;
+    let mut remaining: usize = max_bases;
+    for (file, &size) in files.iter().zip(&sizes) {
+        if remaining == 0 {
+            break;
+        }
+        let (taken, fraction) = sample_file(file, options, remaining).map_err(|error| input_error(file, error))?;
+        remaining = remaining.saturating_sub(taken.iter().map(|record| record.sequence.len()).sum());
+        covered += fraction * size as f64;
+        records.push(taken);
+    }
+    Ok((records, if total == 0 { 1.0 } else { covered / total as f64 }))
+}
+
+// By extension, looking through a .gz suffix
+fn is_sequence_file(path: &Path) -> bool {
+    let path: &Path = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if extension.eq_ignore_ascii_case("gz") => Path::new(path.file_stem().unwrap_or_default()),
+        _ => path,
+    };
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => SEQUENCE_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()),
+        None => false,
+    }
+}
+
+fn expand_directory(directory: &Path, recursive: bool, files: &mut Vec<String>) -> Result<(), KmerError> {
+
+    let unreadable = |error: io::Error| KmerError::Input { file: directory.display().to_string(), error: Box::new(KmerError::Io(error)) };
+    let mut entries: Vec<_> = fs::read_dir(directory).map_err(unreadable)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(unreadable)?;
+
+    // Sort for a reproducible file order
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() {
+            if recursive {
+                expand_directory(&entry, recursive, files)?;
+            } else {
+                warn!("Warning: skipping subdirectory {} (use --recursive)", entry.display());
+            }
+        } else if is_sequence_file(&entry) {
+            files.push(entry.to_string_lossy().into_owned());
+        } else {
+            warn!("Warning: skipping non-sequence file {}", entry.display());
+        }
+    }
+    Ok(())
+}
+
+// Replaces every directory in the input list by the sequence files it contains
+pub fn expand_inputs(inputs: &[String], recursive: bool) -> Result<Vec<String>, KmerError> {
+
+    let mut files: Vec<String> = Vec::new();
+
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            expand_directory(path, recursive, &mut files)?;
+        } else {
+            files.push(input.clone());
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::count::{build_pool, count_kmers, CountOptions};
+    use crate::sample::splitmix64;
+
+    const FASTQ: &[u8] = b"@r1\nACGT\n+\nIIII\n@r2\nGG\n+\nII\n";
+
+    fn parse(data: &[u8], format: Format) -> Result<Vec<Record>, KmerError> {
+        records(data, format).collect()
+    }
+
+    fn fixture(name: &str) -> Result<Vec<Record>, KmerError> {
+        get_records(&format!("fixtures/{}", name), &ReadOptions::default())
+    }
+
+    // Arbitrary bytes, biased towards the characters the parsers look at, must
+    // give records or an error, never a panic
+    #[test]
+    fn random_input_does_not_panic() {
+        const BYTES: &[u8] = b"@>+\n\r ACGTNacgtI!\xc3\xa9\xff";
+        let mut state: u64 = 1;
+        for _ in 0..5_000 {
+            state = splitmix64(state);
+            let length: usize = (state % 64) as usize;
+            let data: Vec<u8> = (0..length).map(|_| {
+                state = splitmix64(state);
+                if state.is_multiple_of(8) { (state >> 8) as u8 } else { BYTES[(state >> 8) as usize % BYTES.len()] }
+            }).collect();
+            for format in [Format::Fasta, Format::Fastq, Format::Raw] {
+                let _ = parse(&data, format);
+            }
+        }
+    }
+
+    // Every prefix of a FASTQ file that does not end at a record boundary
+    // is rejected
+    #[test]
+    fn truncated_fastq_is_an_error() {
+        let boundaries: [usize; 5] = [0, 15, 16, FASTQ.len() - 1, FASTQ.len()];
+        for length in 0..=FASTQ.len() {
+            let result: Result<Vec<Record>, KmerError> = parse(&FASTQ[..length], Format::Fastq);
+            assert_eq!(result.is_ok(), boundaries.contains(&length), "prefix of {} bytes", length);
+        }
+        assert!(parse(b"", Format::Fasta).unwrap().is_empty());
+    }
+
+    #[test]
+    fn empty_file() {
+        assert!(fixture("empty.fq").unwrap().is_empty());
+    }
+
+    #[test]
+    fn empty_record_is_kept() {
+        let records: Vec<Record> = fixture("empty_record.fq").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].sequence, "");
+        assert_eq!(records[1].sequence, "ACGT");
+    }
+
+    #[test]
+    fn malformed_fixtures_are_rejected() {
+        for (name, line) in [("non_ascii.fa", 2), ("quality_length.fq", 4), ("truncated.fq", 3)] {
+            match fixture(name) {
+                Err(KmerError::Input { error, .. }) => {
+                    assert!(matches!(*error, KmerError::Parse { line: found, .. } if found == line), "{}: {}", name, error);
+                }
+                other => panic!("{} was not rejected: {:?}", name, other),
+            }
+        }
+    }
+
+    // Compressed inputs read concurrently give the counts of reading the plain
+    // files one after the other
+    #[test]
+    fn parallel_gzip_matches_serial_plain() {
+        let compressed: Vec<String> = ["tiny.fq.gz", "jellyfish.fa.gz", "tiny.bgzf.fq.gz"].iter().map(|name| format!("fixtures/{}", name)).collect();
+        let plain: Vec<String> = ["tiny.fq", "jellyfish.fa", "tiny.fq"].iter().map(|name| format!("fixtures/{}", name)).collect();
+        let options: ReadOptions = ReadOptions::default();
+
+        let serial: Vec<String> = plain.iter().flat_map(|file| get_records(file, &options).unwrap()).map(|record| record.sequence).collect();
+        let pool: ThreadPool = crate::count::build_pool(3).unwrap();
+        let parallel: Vec<String> = get_records_parallel(&compressed, &options, &pool).unwrap().into_iter().flatten().map(|record| record.sequence).collect();
+        assert_eq!(parallel, serial);
+
+        let count = |sequences: &[String]| -> HashMap<String, u32> { count_kmers(sequences, 5, &pool, &CountOptions::default()).unwrap() };
+        assert_eq!(count(&parallel), count(&serial));
+    }
+
+    // Two FASTAs, a gzipped FASTQ in a subdirectory and an unrelated file: only
+    // the sequence files are counted, the subdirectory only with recursive
+    #[test]
+    fn directories_expand_to_their_sequence_files() {
+        let directory: String = crate::testing::temp_path("inputs");
+        let nested: String = format!("{}/nested", directory);
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(format!("{}/b.fa", directory), ">b\nACGTA\n").unwrap();
+        fs::write(format!("{}/a.fasta", directory), ">a\nTTTT\n").unwrap();
+        fs::write(format!("{}/notes.txt", directory), ">not\nCCCC\n").unwrap();
+        fs::copy("fixtures/tiny.fq.gz", format!("{}/reads.fq.gz", nested)).unwrap();
+
+        let files: Vec<String> = expand_inputs(std::slice::from_ref(&directory), false).unwrap();
+        assert_eq!(files, vec![format!("{}/a.fasta", directory), format!("{}/b.fa", directory)]);
+        let sequences: Vec<String> = files.iter().flat_map(|file| get_records(file, &ReadOptions::default()).unwrap()).map(|record| record.sequence).collect();
+        assert_eq!(sequences, vec!["TTTT", "ACGTA"]);
+
+        let recursive: Vec<String> = expand_inputs(&[directory.clone(), String::from("fixtures/tiny.fq")], true).unwrap();
+        assert_eq!(recursive, vec![
+            format!("{}/a.fasta", directory), format!("{}/b.fa", directory), format!("{}/reads.fq.gz", nested), String::from("fixtures/tiny.fq"),
+        ]);
+
+        fs::remove_dir_all(&directory).unwrap();
+        assert!(!Path::new(&directory).exists());
+    }
+
+
+    // Streamed one record at a time: multi-line FASTA, four-line FASTQ, and
+    // records before a malformed one are yielded before its error
+    #[test]
+    fn records_iterate_lazily() {
+        let fasta: &[u8] = b">a first\nACGT\nAC\n\n>b\nGG\nT\n";
+        let parsed: Vec<(String, String)> = records(fasta, Format::Fasta).map(|record| record.map(|record| (record.id, record.sequence))).collect::<Result<_, _>>().unwrap();
+        assert_eq!(parsed, vec![(String::from("a first"), String::from("ACGTAC")), (String::from("b"), String::from("GGT"))]);
+
+        let file = BufReader::new(File::open("fixtures/tiny.fq").unwrap());
+        let streamed: Vec<Record> = records(file, Format::Fastq).collect::<Result<_, _>>().unwrap();
+        assert_eq!(streamed.len(), 40);
+        for (index, record) in streamed.iter().enumerate() {
+            assert_eq!(record.id, format!("r{}", index));
+            assert_eq!(record.quality.as_ref().map(String::len), Some(record.sequence.len()));
+        }
+        assert_eq!(streamed[0].sequence, "CGTAATGCCTTTCCCTAACAGAGTTTTTCGAACTC");
+        assert_eq!(streamed, fixture("tiny.fq").unwrap());
+
+        let mut broken = records(&b"@r1\nACGT\n+\nIIII\n@r2\nACGT\n+\nII\n"[..], Format::Fastq);
+        assert_eq!(broken.next().unwrap().unwrap().sequence, "ACGT");
+        assert!(broken.next().unwrap().is_err());
+    }
+
+
+    // A .fasta file holding FASTQ: an error under strict, read as FASTQ otherwise
+    #[test]
+    fn mislabeled_files_are_reported() {
+        let path: String = crate::testing::temp_file("mislabeled.fasta", FASTQ);
+        let options: ReadOptions = ReadOptions::default();
+        assert_eq!(resolve_format(&path, &options).unwrap(), Format::Fastq);
+        assert_eq!(get_records(&path, &options).unwrap(), parse(FASTQ, Format::Fastq).unwrap());
+
+        let strict: ReadOptions = ReadOptions { strict: true, ..ReadOptions::default() };
+        match resolve_format(&path, &strict) {
+            Err(KmerError::FormatMismatch { extension, content, .. }) => assert_eq!((extension, content), (Format::Fasta, Format::Fastq)),
+            other => panic!("expected a format mismatch, got {:?}", other),
+        }
+        assert!(matches!(get_records(&path, &strict), Err(KmerError::Input { error, .. }) if matches!(*error, KmerError::FormatMismatch { .. })));
+
+        // --format skips the detection
+        let forced: ReadOptions = ReadOptions { strict: true, format: Some(Format::Fastq), ..ReadOptions::default() };
+        assert!(get_records(&path, &forced).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Every non-empty line is a sequence of its own, header characters included
+    #[test]
+    fn raw_lines_are_sequences() {
+        let path: String = crate::testing::temp_file("raw.txt", b"ACGTA\n\nGGCCA\n>TTT\n");
+        let options: ReadOptions = ReadOptions { format: Some(Format::Raw), ..ReadOptions::default() };
+        let records: Vec<Record> = get_records(&path, &options).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let sequences: Vec<String> = records.iter().map(|record| record.sequence.clone()).collect();
+        assert_eq!(sequences, ["ACGTA", "GGCCA", ">TTT"]);
+        assert!(records.iter().all(|record| record.quality.is_none()));
+
+        let counts: HashMap<String, u32> = count_kmers(&sequences, 5, &build_pool(1).unwrap(), &CountOptions::default()).unwrap();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(Format::parse("raw").unwrap(), Format::Raw);
+    }
+
+    // >seq1 first and >seq1 again share the id seq1
+    #[test]
+    fn duplicate_headers_warn_or_fail() {
+        let records: Vec<Record> = fixture("duplicate_header.fa").unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(check_duplicate_ids(&records, "duplicate_header.fa", false).unwrap(), 1);
+        assert!(matches!(check_duplicate_ids(&records, "duplicate_header.fa", true), Err(KmerError::DuplicateId(id)) if id == "seq1"));
+
+        let strict: ReadOptions = ReadOptions { strict: true, ..ReadOptions::default() };
+        assert!(matches!(get_records(&"fixtures/duplicate_header.fa".to_string(), &strict), Err(KmerError::Input { error, .. }) if matches!(*error, KmerError::DuplicateId(_))));
+        assert_eq!(check_duplicate_ids(&fixture("short.fa").unwrap(), "short.fa", true).unwrap(), 0);
+    }
+
+}
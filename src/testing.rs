@@ -0,0 +1,169 @@
+use std::env;
+use std::fs;
+use std::process;
+
+// Path in the temporary directory, unique per test process and name
+pub(crate) fn temp_path(name: &str) -> String {
+    env::temp_dir().join(format!("kmers_test_{}_{}", process::id(), name)).display().to_string()
+}
+
+// Writes contents to temp_path(name) and returns the path
+pub(crate) fn temp_file(name: &str, contents: &[u8]) -> String {
+    let path: String = temp_path(name);
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+// Parsed JSON value, for checking the JSON outputs without a JSON crate
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    // Keys in document order
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+
+    // Panics on anything but exactly one valid JSON value (and whitespace)
+    pub(crate) fn parse(text: &str) -> Json {
+        let mut parser = JsonParser { bytes: text.as_bytes(), position: 0 };
+        let value: Json = parser.value();
+        parser.whitespace();
+        assert_eq!(parser.position, text.len(), "trailing characters in {}", text);
+        value
+    }
+
+    pub(crate) fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(name, _)| name == key).map(|(_, value)| value).unwrap_or_else(|| panic!("no key {}", key)),
+            other => panic!("not an object: {:?}", other),
+        }
+    }
+
+    pub(crate) fn keys(&self) -> Vec<&str> {
+        match self {
+            Json::Object(entries) => entries.iter().map(|(name, _)| name.as_str()).collect(),
+            other => panic!("not an object: {:?}", other),
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl JsonParser<'_> {
+
+    fn whitespace(&mut self) {
+        while self.bytes.get(self.position).is_some_and(|byte| byte.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: u8) {
+        self.whitespace();
+        assert_eq!(self.bytes.get(self.position), Some(&expected), "expected '{}' at {}", expected as char, self.position);
+        self.position += 1;
+    }
+
+    fn literal(&mut self, literal: &str, value: Json) -> Json {
+        assert!(self.bytes[self.position..].starts_with(literal.as_bytes()), "invalid literal at {}", self.position);
+        self.position += literal.len();
+        value
+    }
+
+    fn value(&mut self) -> Json {
+        self.whitespace();
+        match self.bytes.get(self.position) {
+            Some(b'{') => {
+                let mut entries: Vec<(String, Json)> = Vec::new();
+                self.position += 1;
+                self.whitespace();
+                if self.bytes.get(self.position) == Some(&b'}') {
+                    self.position += 1;
+                    return Json::Object(entries);
+                }
+                loop {
+                    self.whitespace();
+                    let Json::String(key) = self.value() else { panic!("object key is not a string at {}", self.position) };
+                    self.expect(b':');
+                    entries.push((key, self.value()));
+                    self.whitespace();
+                    self.position += 1;
+                    match self.bytes[self.position - 1] {
+                        b',' => continue,
+                        b'}' => return Json::Object(entries),
+                        other => panic!("unexpected '{}' in object", other as char),
+                    }
+                }
+            }
+            Some(b'[') => {
+                let mut items: Vec<Json> = Vec::new();
+                self.position += 1;
+                self.whitespace();
+                if self.bytes.get(self.position) == Some(&b']') {
+                    self.position += 1;
+                    return Json::Array(items);
+                }
+                loop {
+                    items.push(self.value());
+                    self.whitespace();
+                    self.position += 1;
+                    match self.bytes[self.position - 1] {
+                        b',' => continue,
+                        b']' => return Json::Array(items),
+                        other => panic!("unexpected '{}' in array", other as char),
+                    }
+                }
+            }
+            Some(b'"') => {
+                let mut value: String = String::new();
+                self.position += 1;
+                loop {
+                    let c: char = std::str::from_utf8(&self.bytes[self.position..]).unwrap().chars().next().expect("unterminated string");
+                    self.position += c.len_utf8();
+                    match c {
+                        '"' => return Json::String(value),
+                        '\\' => {
+                            let escape: u8 = self.bytes[self.position];
+                            self.position += 1;
+                            match escape {
+                                b'"' => value.push('"'),
+                                b'\\' => value.push('\\'),
+                                b'/' => value.push('/'),
+                                b'n' => value.push('\n'),
+                                b'r' => value.push('\r'),
+                                b't' => value.push('\t'),
+                                b'u' => {
+                                    let code: &str = std::str::from_utf8(&self.bytes[self.position..self.position + 4]).unwrap();
+                                    value.push(char::from_u32(u32::from_str_radix(code, 16).unwrap()).unwrap());
+                                    self.position += 4;
+                                }
+                                other => panic!("invalid escape \\{}", other as char),
+                            }
+                        }
+                        c if (c as u32) < 0x20 => panic!("unescaped control character in string"),
+                        c => value.push(c),
+                    }
+                }
+            }
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(_) => {
+                let start: usize = self.position;
+                while self.bytes.get(self.position).is_some_and(|byte| b"+-.eE0123456789".contains(byte)) {
+                    self.position += 1;
+                }
+                let number: &str = std::str::from_utf8(&self.bytes[start..self.position]).unwrap();
+                Json::Number(number.parse().unwrap_or_else(|_| panic!("invalid number {:?} at {}", number, start)))
+            }
+            None => panic!("unexpected end of JSON"),
+        }
+    }
+}
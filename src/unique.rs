@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::count::{fold_canonical, CountOptions};
+use crate::error::KmerError;
+
+// K-mers of the target table whose count in every other table is at most
+// max_other (0: absent from all of them), sorted by k-mer with the target count
+pub fn unique_kmers(target: &HashMap<String, u64>, others: &[HashMap<String, u64>], max_other: u64) -> Vec<(String, u64)> {
+
+    let mut unique: Vec<(String, u64)> = target.iter()
+        .filter(|(kmer, _)| others.iter().all(|other| other.get(*kmer).copied().unwrap_or(0) <= max_other))
+        .map(|(kmer, &count)| (kmer.clone(), count))
+        .collect();
+    unique.sort_unstable();
+    unique
+}
+
+// Folds a loaded table into canonical k-mers, so tables written with and
+// without --canonical can be compared
+pub fn canonical_table(table: HashMap<String, u64>) -> Result<HashMap<String, u64>, KmerError> {
+    let k: usize = table.keys().next().map_or(0, String::len);
+    fold_canonical(table, k, &CountOptions::default())
+}
+
+pub fn save_unique(unique: &[(String, u64)], path: &str) -> io::Result<()> {
+
+    let mut file = BufWriter::new(File::create(path)?);
+
+    for (kmer, count) in unique {
+        writeln!(file, "{}\t{}", kmer, count)?;
+    }
+
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::load_counts;
+
+    fn fixture_table(name: &str) -> HashMap<String, u64> {
+        load_counts(&format!("fixtures/{}", name)).unwrap()
+    }
+
+    // ACG of legacy.k3.tsv is also in merge_a.tsv, the rest is in neither table
+    #[test]
+    fn target_unique_kmers_of_three_fixtures() {
+        let target: HashMap<String, u64> = fixture_table("legacy.k3.tsv");
+        let others: Vec<HashMap<String, u64>> = vec![fixture_table("merge_a.tsv"), fixture_table("merge_b.tsv")];
+
+        let unique: Vec<(String, u64)> = unique_kmers(&target, &others, 0);
+        assert_eq!(unique, [(String::from("CGT"), 2), (String::from("GTA"), 1), (String::from("TAC"), 1)]);
+        assert_eq!(unique_kmers(&target, &others, 1).len(), 4);
+        assert!(unique_kmers(&others[0], &[target.clone(), others[1].clone()], 0).is_empty());
+    }
+
+    // Canonically CGT is ACG and TAC is GTA, only GTA is left
+    #[test]
+    fn canonical_tables_compare_both_strands() {
+        let target: HashMap<String, u64> = canonical_table(fixture_table("legacy.k3.tsv")).unwrap();
+        assert_eq!(target, HashMap::from([(String::from("ACG"), 5), (String::from("GTA"), 2)]));
+        let others: Vec<HashMap<String, u64>> = vec![canonical_table(fixture_table("merge_a.tsv")).unwrap(), canonical_table(fixture_table("merge_b.tsv")).unwrap()];
+        assert_eq!(unique_kmers(&target, &others, 0), [(String::from("GTA"), 2)]);
+    }
+}
@@ -0,0 +1,33 @@
+// Version line printed by --version, e.g. "kmeRS 0.1.0 (abc1234, 2024-06-01)".
+// The commit hash and build date are embedded by build.rs.
+pub fn version() -> String {
+    format!(
+        "{} {} ({}, {})",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        env!("KMERS_GIT_HASH"),
+        env!("KMERS_BUILD_DATE"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "<name> <major.minor.patch> (<hash or unknown>, <YYYY-MM-DD>)"
+    #[test]
+    fn version_line_parses() {
+        let line: String = version();
+        let (name, rest) = line.split_once(' ').unwrap();
+        assert_eq!(name, env!("CARGO_PKG_NAME"));
+        let (semver, build) = rest.split_once(' ').unwrap();
+        assert_eq!(semver, env!("CARGO_PKG_VERSION"));
+        assert_eq!(semver.split('.').filter(|part| part.parse::<u64>().is_ok()).count(), 3);
+
+        let (hash, date) = build.strip_prefix('(').and_then(|build| build.strip_suffix(')')).unwrap().split_once(", ").unwrap();
+        assert!(hash == "unknown" || (!hash.is_empty() && hash.chars().all(|c| c.is_ascii_hexdigit())), "hash {}", hash);
+        let parts: Vec<u32> = date.split('-').map(|part| part.parse().unwrap()).collect();
+        assert_eq!(parts.len(), 3);
+        assert!(parts[0] >= 2024 && (1..=12).contains(&parts[1]) && (1..=31).contains(&parts[2]), "date {}", date);
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use rayon::{ThreadPool, prelude::*};
+
+use crate::count::{kmer_key, kmer_windows, CountOptions};
+use crate::output::{ordered, Output, SortOrder};
+use crate::quality::{decode_quality, PhredOffset};
+
+// Probability that a base call with this Phred score is correct
+pub fn base_accuracy(score: u8) -> f64 {
+    1.0 - 10f64.powf(-(score as f64) / 10.0)
+}
+
+// Counts where every occurrence of a k-mer adds the probability that all of its
+// bases were called correctly, the product of their accuracies, instead of 1.
+// qualities[i] is the quality string of sequences[i], so this needs FASTQ input.
+pub fn count_weighted_kmers(sequences: &[String], qualities: &[String], k: usize, offset: PhredOffset, pool: &ThreadPool, options: &CountOptions) -> HashMap<String, f64> {
+
+    let kmer_hashmap: Mutex<HashMap<String, f64>> = Mutex::new(HashMap::new());
+
+    pool.install(|| {
+        sequences.par_iter().zip(qualities).for_each(|(sequence, quality)| {
+
+            if options.stopped() {
+                return;
+            }
+
+            let accuracies: Vec<f64> = decode_quality(quality, offset).into_iter().map(base_accuracy).collect();
+            let mut local_map: HashMap<String, f64> = HashMap::new();
+
+            for window in kmer_windows(sequence, k, options) {
+                // Windows are slices of the sequence, their offset is the position
+                let start: usize = window.as_ptr() as usize - sequence.as_ptr() as usize;
+                let Some(window_accuracies) = accuracies.get(start..start + k) else {
+                    continue;
+                };
+                let weight: f64 = window_accuracies.iter().product();
+                *local_map.entry(kmer_key(window, k, options).into_owned()).or_default() += weight;
+            }
+
+            let mut global_map = kmer_hashmap.lock().unwrap();
+            for (key, weight) in local_map {
+                *global_map.entry(key).or_default() += weight;
+            }
+        });
+    });
+
+    Mutex::into_inner(kmer_hashmap).unwrap()
+}
+
+pub fn save_weighted(kmer_hashmap: HashMap<String, f64>, path: &str, buffer: usize, precision: usize, sort: SortOrder) -> io::Result<()> {
+
+    let mut file = Output::create(path, buffer)?;
+
+    for (kmer, weight) in ordered(kmer_hashmap.into_iter(), sort) {
+        writeln!(file, "{}\t{:.*}", kmer, precision, weight)?;
+    }
+
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::count::build_pool;
+    use crate::sequences::{get_records, ReadOptions, Record};
+
+    // ACGTA with the scores 40, 20, 10, 5 and 2: every window weighs the product
+    // of its three accuracies
+    #[test]
+    fn window_weights_are_accuracy_products() {
+        let records: Vec<Record> = get_records(&"fixtures/weighted.fq".to_string(), &ReadOptions::default()).unwrap();
+        let sequences: Vec<String> = records.iter().map(|record| record.sequence.clone()).collect();
+        let qualities: Vec<String> = records.into_iter().map(|record| record.quality.unwrap()).collect();
+        let weights: HashMap<String, f64> = count_weighted_kmers(&sequences, &qualities, 3, PhredOffset::Phred33, &build_pool(1).unwrap(), &CountOptions::default());
+
+        let accuracies: [f64; 5] = [0.9999, 0.99, 0.9, 1.0 - 10f64.powf(-0.5), 1.0 - 10f64.powf(-0.2)];
+        let expected: [(&str, f64); 3] = [("ACG", accuracies[0] * accuracies[1] * accuracies[2]), ("CGT", accuracies[1] * accuracies[2] * accuracies[3]), ("GTA", accuracies[2] * accuracies[3] * accuracies[4])];
+        assert_eq!(weights.len(), 3);
+        for (kmer, weight) in expected {
+            assert!((weights[kmer] - weight).abs() < 1e-12, "{}: {} != {}", kmer, weights[kmer], weight);
+        }
+        assert_eq!(base_accuracy(0), 0.0);
+    }
+}